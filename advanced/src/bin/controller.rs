@@ -0,0 +1,33 @@
+use std::thread;
+use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ channel, RecvTimeoutError };
+
+use advanced::controller::{ Controller, ControllerError };
+
+fn main() -> Result<(), ControllerError> {
+    let (sender, receiver) = channel();
+    let mut controller = Controller::get_controller(0)?;
+    controller.set_event_listener(sender);
+    let state = controller.get_state();
+
+    let is_running = Arc::new(AtomicBool::new(true));
+    let thread_running = is_running.clone();
+    let thread = thread::spawn(move || {
+        controller.start(thread_running)
+    });
+
+    // Reading from an unplugged controller fails, which ends the controller thread and drops the
+    // sender along with the controller. The read error is returned from the join below.
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(event) => println!("Received event: {:?}", event),
+            Err(RecvTimeoutError::Timeout) => println!("{:?}", state.lock().unwrap()),
+            Err(RecvTimeoutError::Disconnected) => break
+        }
+    }
+
+    is_running.store(false, Ordering::Relaxed);
+    thread.join().expect("Failed to join thread")
+}
@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::io::Cursor;
+use std::thread;
+use std::time;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::channel;
+
+use advanced::player::{ Frame, Player };
+
+fn parse_png_from_bytes(png_bytes: Vec<u8>) -> Result<Frame, png::DecodingError> {
+    let cursor = Cursor::new(png_bytes);
+    let decoder = png::Decoder::new(cursor);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut image_data = Vec::new();
+    image_data.resize((info.width * info.height * 4) as usize, 0);
+    reader.next_frame(&mut image_data)?;
+
+    Ok(Frame {
+        width: info.width,
+        height: info.height,
+        data: image_data
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let (sender, receiver) = channel();
+    let mut player = Player::new(receiver);
+
+    let is_sending = Arc::new(AtomicBool::new(true));
+    let is_sending_clone = is_sending.clone();
+    let frames = vec![
+        parse_png_from_bytes(include_bytes!("../test_image.png").to_vec())?,
+        parse_png_from_bytes(include_bytes!("../test_image_2.png").to_vec())?
+    ];
+    let sender_thread = thread::spawn(move || {
+        for frame in frames.iter().cycle() {
+            if !(*is_sending_clone).load(Ordering::Relaxed) {
+                break;
+            }
+            let sent = sender.send(Frame {
+                width: frame.width,
+                height: frame.height,
+                data: frame.data.clone()
+            });
+            if sent.is_err() {
+                break;
+            }
+            thread::sleep(time::Duration::from_millis(1000));
+        }
+    });
+
+    let result = player.run();
+
+    is_sending.store(false, Ordering::Relaxed);
+    sender_thread.join().expect("Frame sender panicked");
+
+    Ok(result?)
+}
@@ -0,0 +1,24 @@
+use std::env;
+use std::error::Error;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use advanced::player::Player;
+use advanced::video::VideoStream;
+use advanced::video_capture::CaptureReader;
+
+// Plays a video capture taken with the L2 button, optionally sped up: tello-replay <capture> [speed]
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let path = args.next().ok_or("Usage: tello-replay <capture> [speed]")?;
+    let speed = match args.next() {
+        Some(speed) => speed.parse()?,
+        None => 1.0
+    };
+
+    let (sender, receiver) = channel();
+    let player = Player::new(receiver);
+    let _replay = VideoStream::replay(CaptureReader::open(&path)?, speed, Duration::from_millis(100), sender)?;
+
+    Ok(player.run()?)
+}
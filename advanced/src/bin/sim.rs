@@ -0,0 +1,595 @@
+extern crate gstreamer as gst;
+extern crate gstreamer_app as gst_app;
+
+use gst::prelude::*;
+
+use std::env;
+use std::net::{ SocketAddr, UdpSocket };
+use std::thread;
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::{ Duration, Instant };
+
+use advanced::protocol::{ TelloGram, TelloGramDirection, PackageType, SpeedMode, VideoMode };
+use advanced::flight_data::{ FlightData, SensorState, FlightState, FrontState };
+use advanced::log_data::{ self, LogRecord, MvoRecord, ImuRecord };
+use advanced::file_transfer::{ self, FileChunk, FileInfo, CHUNK_SIZE, CHUNKS_PER_PIECE };
+
+const DEFAULT_BIND_ADDR: &str = "127.0.0.1:8889";
+
+const TELEMETRY_INTERVAL: Duration = Duration::from_millis(100);
+const LOG_HEADER_INTERVAL: Duration = Duration::from_secs(1);
+const LOG_ID: u16 = 0x029d;
+const WIFI_INTERVAL: Duration = Duration::from_secs(1);
+const FIRMWARE_VERSION: &str = "01.04.92.01";
+const LOADER_VERSION: &str = "01.00.00.10";
+
+const VIDEO_PACKET_SIZE: usize = 1460;
+const VIDEO_PIPELINE: &str = "videotestsrc is-live=true pattern=ball \
+    ! capsfilter name=size caps=video/x-raw,width=960,height=720,framerate=30/1 \
+    ! x264enc tune=zerolatency speed-preset=ultrafast key-int-max=30 bitrate=1500 \
+    ! h264parse config-interval=-1 \
+    ! video/x-h264,stream-format=byte-stream,alignment=au \
+    ! appsink name=sink sync=true";
+const PICTURE_PIPELINE: &str = "videotestsrc num-buffers=1 pattern=smpte \
+    ! video/x-raw,width=2592,height=1936 \
+    ! jpegenc \
+    ! appsink name=sink";
+const PICTURE_TIMEOUT: u64 = 5000;
+
+const TAKEOFF_HEIGHT: f32 = 1.2;
+const MAX_SPEED: f32 = 2.0;
+const MAX_FAST_SPEED: f32 = 4.0;
+const MAX_CLIMB_RATE: f32 = 1.0;
+const MAX_YAW_RATE: f32 = 1.5;
+const BATTERY_DRAIN_PER_SECOND: f32 = 100.0 / (13.0 * 60.0);
+
+struct Flight {
+    is_flying: bool,
+    position: [f32; 3],
+    velocity: [f32; 3],
+    yaw: f32,
+    axes: [f32; 4],
+    speed_mode: SpeedMode,
+    battery: f32,
+    fly_time: f32
+}
+
+impl Flight {
+    fn new() -> Flight {
+        Flight {
+            is_flying: false,
+            position: [0.0; 3],
+            velocity: [0.0; 3],
+            yaw: 0.0,
+            axes: [0.0; 4],
+            speed_mode: SpeedMode::Slow,
+            battery: 100.0,
+            fly_time: 0.0
+        }
+    }
+
+    fn takeoff(&mut self) {
+        if !self.is_flying && self.battery > 0.0 {
+            self.is_flying = true;
+            self.position[2] = TAKEOFF_HEIGHT;
+        }
+    }
+
+    fn land(&mut self) {
+        self.is_flying = false;
+        self.position[2] = 0.0;
+        self.velocity = [0.0; 3];
+    }
+
+    fn step(&mut self, dt: f32) {
+        if !self.is_flying {
+            return;
+        }
+
+        // Stick axes arrive in packet order: roll, pitch, throttle, yaw
+        let [roll, pitch, throttle, yaw_rate] = self.axes;
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let max_speed = if self.speed_mode == SpeedMode::Fast { MAX_FAST_SPEED } else { MAX_SPEED };
+        self.velocity = [
+            max_speed * (pitch * cos_yaw - roll * sin_yaw),
+            max_speed * (pitch * sin_yaw + roll * cos_yaw),
+            MAX_CLIMB_RATE * throttle
+        ];
+        self.yaw += MAX_YAW_RATE * yaw_rate * dt;
+
+        for i in 0..3 {
+            self.position[i] += self.velocity[i] * dt;
+        }
+        self.position[2] = self.position[2].max(0.0);
+
+        self.fly_time += dt;
+        self.battery = (self.battery - BATTERY_DRAIN_PER_SECOND * dt).max(0.0);
+        if self.battery == 0.0 {
+            self.land();
+        }
+    }
+
+    // The signal fades as the drone flies away from where it took off
+    fn wifi_strength(&self) -> u8 {
+        let distance = (self.position[0].powi(2) + self.position[1].powi(2)).sqrt();
+        (100.0 - distance).max(0.0) as u8
+    }
+
+    fn flight_data(&self) -> FlightData {
+        let decimeters = |meters: f32| (meters * 10.0) as i16;
+
+        let mut flight_state = if self.is_flying { FlightState::EM_SKY } else { FlightState::EM_GROUND };
+        if self.is_flying && self.axes.iter().all(|axis| *axis == 0.0) {
+            flight_state |= FlightState::DRONE_HOVER;
+        }
+        if self.battery < 10.0 {
+            flight_state |= FlightState::BATTERY_LOWER;
+        }
+        if self.battery < 20.0 {
+            flight_state |= FlightState::BATTERY_LOW;
+        }
+
+        FlightData {
+            height: decimeters(self.position[2]),
+            north_speed: decimeters(self.velocity[0]),
+            east_speed: decimeters(self.velocity[1]),
+            ground_speed: decimeters(self.velocity[2]),
+            fly_time: (self.fly_time * 10.0) as i16,
+
+            sensors: SensorState::IMU | SensorState::PRESSURE | SensorState::DOWN_VISUAL
+                | SensorState::POWER | SensorState::BATTERY | SensorState::GRAVITY,
+            imu_calibration_state: 0,
+
+            battery_percentage: self.battery as u8,
+            drone_fly_time_left: 0,
+            drone_battery_left: (3600.0 + 6.0 * self.battery) as i16,
+
+            flight_state,
+            fly_mode: if self.is_flying { 6 } else { 1 },
+            throw_fly_timer: 0,
+            camera_state: 0,
+            electrical_machinery_state: 0,
+
+            front: FrontState::empty(),
+            temperature_height: false
+        }
+    }
+
+    fn log_records(&self) -> Vec<LogRecord> {
+        let centimeters_per_second = |meters: f32| Some((meters * 100.0) as i16);
+        let (sin_half_yaw, cos_half_yaw) = (self.yaw / 2.0).sin_cos();
+
+        vec![
+            LogRecord::Mvo(MvoRecord {
+                velocity_x: centimeters_per_second(self.velocity[0]),
+                velocity_y: centimeters_per_second(self.velocity[1]),
+                velocity_z: centimeters_per_second(self.velocity[2]),
+                position_x: Some(self.position[0]),
+                position_y: Some(self.position[1]),
+                position_z: Some(self.position[2])
+            }),
+            LogRecord::Imu(ImuRecord {
+                acceleration: [0.0, 0.0, -1.0],
+                gyro: [0.0, 0.0, if self.is_flying { MAX_YAW_RATE * self.axes[3] } else { 0.0 }],
+                quaternion: [cos_half_yaw, 0.0, 0.0, sin_half_yaw],
+                velocity: self.velocity,
+                temperature: 45.0
+            })
+        ]
+    }
+}
+
+struct Simulator {
+    socket: UdpSocket,
+    client: Option<SocketAddr>,
+    video_destination: Arc<Mutex<Option<SocketAddr>>>,
+    video_mode: Arc<Mutex<VideoMode>>,
+    seq_nr: u16,
+
+    flight: Flight,
+    max_height: u16,
+    low_battery_threshold: u8,
+    attitude_angle: f32,
+    picture: Option<(FileInfo, Vec<u8>)>,
+    next_file_id: u16,
+    log_acked: bool,
+    last_telemetry: Instant,
+    last_log_header: Instant,
+    last_wifi: Instant
+}
+
+impl Simulator {
+    fn new(socket: UdpSocket, video_destination: Arc<Mutex<Option<SocketAddr>>>, video_mode: Arc<Mutex<VideoMode>>) -> Simulator {
+        let now = Instant::now();
+        Simulator {
+            socket,
+            client: None,
+            video_destination,
+            video_mode,
+            seq_nr: 0,
+
+            flight: Flight::new(),
+            max_height: 10,
+            low_battery_threshold: 10,
+            attitude_angle: 25.0,
+            picture: None,
+            next_file_id: 1,
+            log_acked: false,
+            last_telemetry: now,
+            last_log_header: now,
+            last_wifi: now
+        }
+    }
+
+    fn send(&mut self, packet_type: PackageType, id: u16, seq: u16, payload: &[u8]) {
+        if let Some(client) = self.client {
+            let gram = TelloGram::new(TelloGramDirection::FromDrone, packet_type, 0, id, seq, payload);
+            if let Err(e) = self.socket.send_to(&gram.to_bytes(), client) {
+                println!("Failed to send message 0x{:x} to {}: {}", id, client, e);
+            }
+        }
+    }
+
+    fn send_next(&mut self, packet_type: PackageType, id: u16, payload: &[u8]) {
+        let seq = self.seq_nr;
+        self.seq_nr = self.seq_nr.wrapping_add(1);
+        self.send(packet_type, id, seq, payload);
+    }
+
+    fn acknowledge(&mut self, gram: &TelloGram) {
+        self.respond(gram, &[]);
+    }
+
+    // Responses echo the id and sequence of the request, with a result code of 0 before the value
+    fn respond(&mut self, gram: &TelloGram, value: &[u8]) {
+        let packet_type = gram.packet_type().unwrap_or(PackageType::Set);
+        let mut payload = vec![0];
+        payload.extend_from_slice(value);
+        self.send(packet_type, gram.id(), gram.sequence(), &payload);
+    }
+
+    fn take_picture(&mut self) {
+        let jpeg = match capture_picture() {
+            Ok(jpeg) => jpeg,
+            Err(e) => {
+                println!("Failed to take picture: {}", e);
+                return;
+            }
+        };
+
+        let info = FileInfo { file_type: 1, size: jpeg.len() as u32, file_id: self.next_file_id };
+        self.next_file_id = self.next_file_id.wrapping_add(1);
+        self.picture = Some((info, jpeg));
+        self.send_next(PackageType::Data1, 0x62, &info.to_bytes());
+    }
+
+    // Like the real drone, the next piece is only sent once the previous one has been acked
+    fn send_piece(&mut self, piece: u32) {
+        let chunks: Vec<Vec<u8>> = match &self.picture {
+            Some((info, jpeg)) => jpeg.chunks(CHUNK_SIZE)
+                .enumerate()
+                .skip((piece * CHUNKS_PER_PIECE) as usize)
+                .take(CHUNKS_PER_PIECE as usize)
+                .map(|(chunk, data)| FileChunk { file_id: info.file_id, piece, chunk: chunk as u32, data: data.to_vec() }.to_bytes())
+                .collect(),
+            None => return
+        };
+
+        for chunk in chunks {
+            self.send_next(PackageType::Data1, 0x63, &chunk);
+        }
+    }
+
+    fn handle_connect(&mut self, request: &[u8], from: SocketAddr) {
+        const CONNECT_REQUEST: &[u8] = b"conn_req:";
+        if request.len() < CONNECT_REQUEST.len() + 2 {
+            println!("Received truncated connection request {:?}", request);
+            return;
+        }
+
+        let port_bytes = &request[CONNECT_REQUEST.len()..CONNECT_REQUEST.len() + 2];
+        let video_port = u16::from_le_bytes([port_bytes[0], port_bytes[1]]);
+        println!("Client {} connected, streaming video to port {}", from, video_port);
+
+        self.client = Some(from);
+        self.log_acked = false;
+        *self.video_destination.lock().unwrap() = Some(SocketAddr::new(from.ip(), video_port));
+
+        let mut ack = b"conn_ack:".to_vec();
+        ack.extend_from_slice(port_bytes);
+        if let Err(e) = self.socket.send_to(&ack, from) {
+            println!("Failed to acknowledge connection: {}", e);
+        }
+    }
+
+    fn handle_gram(&mut self, gram: TelloGram) {
+        match gram.id() {
+            0x25 => (), // SPS and PPS are already repeated in front of every key frame
+            0x20 => {
+                println!("Video bitrate {:?}", gram.payload().first());
+                self.acknowledge(&gram);
+            },
+            0x31 => {
+                let mode = match gram.payload() {
+                    &[0] => VideoMode::Normal,
+                    &[1] => VideoMode::Wide,
+                    payload => return println!("Received invalid video mode {:?}", payload)
+                };
+                println!("Video mode {:?}", mode);
+                *self.video_mode.lock().unwrap() = mode;
+                self.acknowledge(&gram);
+            },
+            0x34 => {
+                println!("Exposure {:?}", gram.payload().first());
+                self.acknowledge(&gram);
+            },
+            0x30 => {
+                println!("Taking picture");
+                self.acknowledge(&gram);
+                self.take_picture();
+            },
+            0x62 => self.send_piece(0),
+            0x63 => {
+                let next_piece = match (&self.picture, file_transfer::parse_piece_ack(gram.payload())) {
+                    (Some((info, _)), Some((file_id, piece))) if file_id == info.file_id && piece + 1 < info.piece_count() => piece + 1,
+                    _ => return
+                };
+                self.send_piece(next_piece);
+            },
+            0x64 => {
+                if gram.packet_type() != Some(PackageType::Get) {
+                    println!("Received file done with wrong packet type {:?}", gram.packet_type());
+                } else if let Some((info, _)) = self.picture.take() {
+                    println!("Picture of {} bytes downloaded", info.size);
+                }
+            },
+            0x45 => self.respond(&gram, FIRMWARE_VERSION.as_bytes()),
+            0x49 => self.respond(&gram, LOADER_VERSION.as_bytes()),
+            0x50 => {
+                if let Some(axes) = TelloGram::joystick_axes(gram.payload()) {
+                    self.flight.axes = axes;
+                }
+                if let Some(speed_mode) = TelloGram::joystick_speed_mode(gram.payload()) {
+                    self.flight.speed_mode = speed_mode;
+                }
+            },
+            0x54 => {
+                println!("Taking off");
+                self.flight.takeoff();
+                self.acknowledge(&gram);
+            },
+            0x55 => {
+                println!("Landing");
+                self.flight.land();
+                self.acknowledge(&gram);
+            },
+            0x5c => {
+                println!("Flipping {:?}", gram.payload().first());
+                self.acknowledge(&gram);
+            },
+            0x5d => {
+                // There is nobody to throw the simulated drone, so it takes off right away
+                println!("Throw takeoff");
+                self.flight.takeoff();
+                self.acknowledge(&gram);
+            },
+            0x5e => {
+                println!("Palm landing");
+                self.flight.land();
+                self.acknowledge(&gram);
+            },
+            0x58 => {
+                if let &[low, high] = gram.payload() {
+                    self.max_height = u16::from_le_bytes([low, high]);
+                    self.acknowledge(&gram);
+                }
+            },
+            0x1055 => {
+                if let &[threshold] = gram.payload() {
+                    self.low_battery_threshold = threshold;
+                    self.acknowledge(&gram);
+                }
+            },
+            0x1056 => self.respond(&gram, &self.max_height.to_le_bytes()),
+            0x1057 => self.respond(&gram, &[self.low_battery_threshold]),
+            0x1058 => {
+                if let &[b0, b1, b2, b3] = gram.payload() {
+                    self.attitude_angle = f32::from_le_bytes([b0, b1, b2, b3]);
+                    self.acknowledge(&gram);
+                }
+            },
+            0x1059 => self.respond(&gram, &self.attitude_angle.to_le_bytes()),
+            0x1050 => {
+                let payload = gram.payload();
+                if payload.len() >= 3 && u16::from_le_bytes([payload[1], payload[2]]) == LOG_ID {
+                    self.log_acked = true;
+                } else {
+                    println!("Received log header ack with wrong log id {:?}", payload);
+                }
+            },
+            id => println!("Unhandled message 0x{:x} {:?}", id, gram.payload())
+        }
+    }
+
+    fn tick(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_telemetry);
+        if elapsed < TELEMETRY_INTERVAL {
+            return;
+        }
+        self.last_telemetry = now;
+
+        self.flight.step(elapsed.as_secs_f32());
+        if self.client.is_none() {
+            return;
+        }
+
+        let flight_data = self.flight.flight_data().to_bytes();
+        self.send_next(PackageType::Get, 0x56, &flight_data);
+
+        if self.log_acked {
+            let log_data = log_data::encode(&self.flight.log_records(), (self.seq_nr & 0xff) as u8);
+            self.send_next(PackageType::Data1, 0x1051, &log_data);
+        } else if now.duration_since(self.last_log_header) >= LOG_HEADER_INTERVAL {
+            self.last_log_header = now;
+            let log_id = LOG_ID.to_le_bytes();
+            self.send_next(PackageType::Data1, 0x1050, &[log_id[0], log_id[1], 0, 0, 2, 0, 1, 0, 0, 0, 0, 0]);
+        }
+
+        if now.duration_since(self.last_wifi) >= WIFI_INTERVAL {
+            self.last_wifi = now;
+            self.send_next(PackageType::Data1, 0x1a, &[self.flight.wifi_strength(), 0]);
+        }
+    }
+
+    fn run(&mut self, is_running: Arc<AtomicBool>) {
+        let mut buffer = [0u8; 4096];
+        while is_running.load(Ordering::Relaxed) {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((num_bytes, from)) => {
+                    let message = &buffer[..num_bytes];
+                    if message.starts_with(b"conn_req:") {
+                        self.handle_connect(message, from);
+                    } else {
+                        match TelloGram::parse(message) {
+                            Ok(gram) => self.handle_gram(gram),
+                            Err(e) => println!("Received invalid TelloGram ({:?}) {:?}", e, message)
+                        }
+                    }
+                },
+                Err(_) => () // Read timeout, used to keep the telemetry ticking
+            }
+
+            self.tick();
+        }
+    }
+}
+
+fn launch_pipeline(description: &str) -> Result<(gst::Pipeline, gst_app::AppSink), String> {
+    gst::init().map_err(|e| format!("Failed to init gstreamer: {}", e))?;
+
+    let pipeline = gst::parse_launch(description)
+        .map_err(|e| format!("Failed to create pipeline: {}", e))?
+        .dynamic_cast::<gst::Pipeline>()
+        .map_err(|_| String::from("Pipeline is not a pipeline"))?;
+    let appsink = pipeline.get_by_name("sink")
+        .ok_or_else(|| String::from("Pipeline has no sink"))?
+        .dynamic_cast::<gst_app::AppSink>()
+        .map_err(|_| String::from("Sink is not an appsink"))?;
+    Ok((pipeline, appsink))
+}
+
+// Encodes a single test pattern frame at the resolution of the real camera
+fn capture_picture() -> Result<Vec<u8>, String> {
+    let (pipeline, appsink) = launch_pipeline(PICTURE_PIPELINE)?;
+    pipeline.set_state(gst::State::Playing).map_err(|e| format!("Failed to start picture pipeline: {:?}", e))?;
+
+    let picture = appsink.try_pull_sample(gst::ClockTime::from_mseconds(PICTURE_TIMEOUT))
+        .ok_or_else(|| String::from("Picture pipeline produced no picture"))
+        .and_then(|sample| {
+            let buffer = sample.get_buffer().ok_or_else(|| String::from("Picture sample has no buffer"))?;
+            let map = buffer.map_readable().map_err(|e| format!("Failed to map picture buffer: {}", e))?;
+            Ok(map.as_slice().to_vec())
+        });
+
+    pipeline.set_state(gst::State::Null).map_err(|e| format!("Failed to stop picture pipeline: {:?}", e))?;
+    picture
+}
+
+fn video_caps(mode: VideoMode) -> gst::Caps {
+    let width: i32 = match mode {
+        VideoMode::Normal => 960,
+        VideoMode::Wide => 1280,
+    };
+    gst::Caps::new_simple("video/x-raw", &[
+        ("width", &width),
+        ("height", &720i32),
+        ("framerate", &gst::Fraction::new(30, 1))
+    ])
+}
+
+fn stream_video(is_running: Arc<AtomicBool>, video_destination: Arc<Mutex<Option<SocketAddr>>>, video_mode: Arc<Mutex<VideoMode>>) -> Result<(), String> {
+    let (pipeline, appsink) = launch_pipeline(VIDEO_PIPELINE)?;
+    let size = pipeline.get_by_name("size").ok_or_else(|| String::from("Video pipeline has no size filter"))?;
+    let mut current_mode = VideoMode::Normal;
+
+    let video_socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Failed to create video socket: {}", e))?;
+
+    pipeline.set_state(gst::State::Playing).map_err(|e| format!("Failed to start video pipeline: {:?}", e))?;
+
+    // Like the real drone, every frame is split into numbered pieces with a two byte header:
+    // the frame number followed by the piece index, where the top bit marks the last piece.
+    let mut frame_nr: u8 = 0;
+    while is_running.load(Ordering::Relaxed) {
+        // The encoder renegotiates and sends a new SPS for the changed resolution
+        let mode = *video_mode.lock().unwrap();
+        if mode != current_mode {
+            size.set_property("caps", &video_caps(mode)).map_err(|e| format!("Failed to change video size: {}", e))?;
+            current_mode = mode;
+        }
+
+        let sample = match appsink.try_pull_sample(gst::ClockTime::from_mseconds(100)) {
+            Some(sample) => sample,
+            None => continue
+        };
+
+        let destination = match *video_destination.lock().unwrap() {
+            Some(destination) => destination,
+            None => continue
+        };
+
+        let buffer = match sample.get_buffer() {
+            Some(buffer) => buffer,
+            None => continue
+        };
+        let map = buffer.map_readable().map_err(|e| format!("Failed to map video buffer: {}", e))?;
+
+        let pieces: Vec<&[u8]> = map.as_slice().chunks(VIDEO_PACKET_SIZE - 2).collect();
+        for (index, piece) in pieces.iter().enumerate() {
+            let mut piece_index = (index & 0x7f) as u8;
+            if index == pieces.len() - 1 {
+                piece_index |= 0x80;
+            }
+
+            let mut packet = Vec::with_capacity(piece.len() + 2);
+            packet.push(frame_nr);
+            packet.push(piece_index);
+            packet.extend_from_slice(piece);
+
+            if let Err(e) = video_socket.send_to(&packet, destination) {
+                println!("Failed to send video packet: {}", e);
+            }
+        }
+        frame_nr = frame_nr.wrapping_add(1);
+    }
+
+    pipeline.set_state(gst::State::Null).map_err(|e| format!("Failed to stop video pipeline: {:?}", e))?;
+    Ok(())
+}
+
+fn main() {
+    let bind_addr = env::args().nth(1).unwrap_or_else(|| String::from(DEFAULT_BIND_ADDR));
+
+    let socket = UdpSocket::bind(&bind_addr).expect("Failed to bind simulator command socket");
+    socket.set_read_timeout(Some(Duration::from_millis(10))).expect("Failed to set read timeout");
+    println!("Simulated Tello listening on {}", bind_addr);
+
+    let is_running = Arc::new(AtomicBool::new(true));
+    let video_destination = Arc::new(Mutex::new(None));
+    let video_mode = Arc::new(Mutex::new(VideoMode::Normal));
+
+    let video_thread_running = is_running.clone();
+    let video_thread_destination = video_destination.clone();
+    let video_thread_mode = video_mode.clone();
+    let video_thread = thread::spawn(move || {
+        if let Err(e) = stream_video(video_thread_running, video_thread_destination, video_thread_mode) {
+            println!("Video streaming disabled: {}", e);
+        }
+    });
+
+    Simulator::new(socket, video_destination, video_mode).run(is_running.clone());
+
+    is_running.store(false, Ordering::Relaxed);
+    video_thread.join().unwrap();
+}
@@ -0,0 +1,175 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use chrono::Local;
+
+use advanced::controller;
+use advanced::player;
+use advanced::protocol::{ FlipDirection, SpeedMode };
+use advanced::tello::{ Tello, TelloConfig, Telemetry };
+
+fn speed_mode_status(speed_mode: SpeedMode) -> String {
+    format!("{:?} mode", speed_mode)
+}
+
+// Returns the name of the file the picture was saved to
+fn save_picture(tello: &Tello) -> Result<String, Box<dyn Error>> {
+    let picture = tello.take_picture()?;
+    let file_name = Local::now().format("tello-%Y%m%d-%H%M%S.jpg").to_string();
+    fs::write(&file_name, picture)?;
+    Ok(file_name)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let is_running = Arc::new(AtomicBool::new(true));
+
+    let (controller_events_sender, controller_events_receiver) = channel();
+
+    let mut controller = controller::Controller::get_controller(0)?;
+    controller.set_event_listener(controller_events_sender);
+    let controller_state = controller.get_state();
+    let controller_is_running = is_running.clone();
+    let controller_thread = thread::spawn(move || {
+        if let Err(e) = controller.start(controller_is_running) {
+            println!("Controller stopped: {}", e);
+        }
+    });
+
+    let mut config = TelloConfig::new();
+    if let Some(drone_addr) = env::args().nth(1) {
+        let drone_addr: SocketAddr = drone_addr.parse()
+            .map_err(|e| format!("Drone address must be of the form ip:port ({})", e))?;
+        config = config.drone_ip(drone_addr.ip()).remote_cmd_port(drone_addr.port());
+    }
+    let mut tello = Tello::connect(config)?;
+    // Logged so every flight records the firmware it ran on
+    match (tello.get_version(), tello.get_loader_version()) {
+        (Ok(firmware), Ok(loader)) => println!("Tello firmware {}, loader {}", firmware, loader),
+        (Err(e), _) | (_, Err(e)) => println!("Failed to query the Tello firmware version: {}", e),
+    }
+
+    let (video_sender, video_receiver) = channel();
+    let mut player = player::Player::new(video_receiver);
+    tello.start_video(video_sender)?;
+
+    // Keeps the speed mode shown in the player in step with the drone state
+    let (status_sender, status_receiver) = channel();
+    let (telemetry_sender, telemetry_receiver) = channel();
+    player.set_status_receiver(status_receiver);
+    status_sender.send(speed_mode_status(tello.speed_mode()))?;
+    tello.set_telemetry_listener(telemetry_sender);
+    let status_thread = thread::spawn(move || {
+        for telemetry in telemetry_receiver {
+            if let Telemetry::SpeedMode(speed_mode) = telemetry {
+                if status_sender.send(speed_mode_status(speed_mode)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    let tello = Arc::new(tello);
+
+    let tello_cmd_loop_running = is_running.clone();
+    let tello_cmd_loop = thread::spawn(move || {
+        let mut picture_threads = vec![];
+        while (*tello_cmd_loop_running).load(Ordering::Relaxed) {
+            let result = if let Ok(event) = controller_events_receiver.recv_timeout(Duration::from_millis(15)) {
+                // Acks are retried in the background, the loop keeps streaming stick input meanwhile
+                match event {
+                    controller::Event::EmergencyStop => tello.emergency().map(|_| ()),
+                    controller::Event::XPress => tello.takeoff().map(|_| ()),
+                    controller::Event::CirclePress => tello.land().map(|_| ()),
+                    controller::Event::SquarePress => tello.throw_takeoff().map(|_| ()),
+                    controller::Event::TrianglePress => tello.palm_land().map(|_| ()),
+                    controller::Event::OptionsPress => {
+                        println!("Speed mode: {:?}", tello.toggle_speed_mode());
+                        Ok(())
+                    },
+                    controller::Event::SharePress => {
+                        // The download takes a few seconds, which must not hold up the stick input
+                        let tello = tello.clone();
+                        picture_threads.push(thread::spawn(move || match save_picture(&tello) {
+                            Ok(file_name) => println!("Saved picture to {}", file_name),
+                            Err(e) => println!("Failed to take picture: {}", e),
+                        }));
+                        Ok(())
+                    },
+                    controller::Event::R2Press => {
+                        if tello.is_recording() {
+                            tello.stop_recording().map(|_| println!("Stopped recording"))
+                        } else {
+                            let file_name = Local::now().format("tello-%Y%m%d-%H%M%S.mkv").to_string();
+                            tello.start_recording(&file_name).map(|_| println!("Recording to {}", file_name))
+                        }
+                    },
+                    controller::Event::L2Press => {
+                        if tello.is_capturing() {
+                            tello.stop_capture().map(|_| println!("Stopped capturing video packets"))
+                        } else {
+                            let file_name = Local::now().format("tello-%Y%m%d-%H%M%S.tcap").to_string();
+                            tello.start_capture(&file_name).map(|_| println!("Capturing video packets to {}", file_name))
+                        }
+                    },
+                    controller::Event::LeftHat => tello.flip(FlipDirection::Left).map(|_| ()),
+                    controller::Event::UpHat => tello.flip(FlipDirection::Forward).map(|_| ()),
+                    controller::Event::RightHat => tello.flip(FlipDirection::Right).map(|_| ()),
+                    controller::Event::DownHat => tello.flip(FlipDirection::Backward).map(|_| ()),
+                }
+            } else {
+                let joystick = *controller_state.lock().unwrap();
+                tello.set_joystick(joystick)
+            };
+
+            if let Err(e) = result {
+                println!("Failed to send command: {}", e);
+            }
+        }
+
+        // Pending downloads hold on to the Tello, which has to be released for the shutdown
+        for picture_thread in picture_threads {
+            picture_thread.join().expect("Picture download panicked");
+        }
+        tello
+    });
+
+    // The player exits the process once its window closes, so the shutdown has to run from its exit handler
+    player.set_exit_handler(move || {
+        is_running.store(false, Ordering::Relaxed);
+
+        let tello = tello_cmd_loop.join().expect("Tello command loop panicked");
+        // Closing the window mid-recording must still leave a playable file
+        if tello.is_recording() {
+            match tello.stop_recording() {
+                Ok(()) => println!("Stopped recording"),
+                Err(e) => println!("Failed to finalize recording: {}", e),
+            }
+        }
+        if tello.is_capturing() {
+            if let Err(e) = tello.stop_capture() {
+                println!("Failed to finish video capture: {}", e);
+            }
+        }
+
+        match Arc::try_unwrap(tello) {
+            Ok(mut tello) => {
+                if let Err(e) = tello.shutdown() {
+                    println!("Failed to shut down Tello: {}", e);
+                }
+            },
+            Err(_) => println!("Tello is still in use and could not be shut down")
+        }
+
+        status_thread.join().expect("Status thread panicked");
+        controller_thread.join().expect("Controller thread panicked");
+    });
+
+    Ok(player.run()?)
+}
+
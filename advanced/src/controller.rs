@@ -2,12 +2,13 @@ extern crate nix;
 
 use std::io;
 use std::fs;
-use std::thread;
+use std::fmt;
+use std::error::Error;
 use std::path::Path;
-use std::time::Duration;
+use nix::errno::Errno;
 use nix::sys::select::{ select, FdSet };
 use nix::sys::time::{ TimeVal, TimeValLike };
-use std::sync::mpsc::{ channel, Sender };
+use std::sync::mpsc::Sender;
 use std::os::unix::io::IntoRawFd;
 use std::sync::{ Arc, Mutex };
 use std::sync::atomic::{ AtomicBool, Ordering };
@@ -15,7 +16,45 @@ use std::sync::atomic::{ AtomicBool, Ordering };
 use evdev_rs as evdev;
 use evdev::enums::{ EventCode, EV_KEY, EV_ABS };
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Debug)]
+pub enum ControllerError {
+    Io(io::Error),
+    NotFound(usize),
+    NoFileDescriptor,
+    Select(nix::Error),
+    Read(io::Error),
+}
+
+impl fmt::Display for ControllerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControllerError::Io(e) => write!(f, "Failed to access input device: {}", e),
+            ControllerError::NotFound(index) => write!(f, "No controller found at index {}", index),
+            ControllerError::NoFileDescriptor => write!(f, "Input device has no file descriptor"),
+            ControllerError::Select(e) => write!(f, "Failed to wait for input events: {}", e),
+            ControllerError::Read(e) => write!(f, "Failed to read input event: {}", e),
+        }
+    }
+}
+
+impl Error for ControllerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ControllerError::Io(e) => Some(e),
+            ControllerError::Select(e) => Some(e),
+            ControllerError::Read(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ControllerError {
+    fn from(e: io::Error) -> ControllerError {
+        ControllerError::Io(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct State {
     pub joystick_left_x: f32,
     pub joystick_left_y: f32,
@@ -34,10 +73,34 @@ impl State {
     }
 }
 
+// L1 + R1 + Circle have to be held together to stop the motors, so it cannot happen by accident
+#[derive(Default)]
+struct EmergencyCombination {
+    l1: bool,
+    r1: bool,
+    circle: bool
+}
+
+impl EmergencyCombination {
+    // Returns whether this key press completed the combination. Key values are 1 on press,
+    // 2 on auto repeat and 0 on release.
+    fn update(&mut self, key: &EV_KEY, value: i32) -> bool {
+        let held = value != 0;
+        match key {
+            EV_KEY::BTN_TL => self.l1 = held,
+            EV_KEY::BTN_TR => self.r1 = held,
+            EV_KEY::BTN_EAST => self.circle = held,
+            _ => return false
+        }
+        value == 1 && self.l1 && self.r1 && self.circle
+    }
+}
+
 pub struct Controller {
     device: evdev::Device,
     event_channel: Option<Sender<Event>>,
-    state: Arc<Mutex<State>>
+    state: Arc<Mutex<State>>,
+    emergency_combination: EmergencyCombination
 }
 
 #[derive(Debug)]
@@ -46,11 +109,17 @@ pub enum Event {
     SquarePress,
     TrianglePress,
     CirclePress,
+    OptionsPress,
+    SharePress,
+    R2Press,
+    L2Press,
 
     LeftHat,
     UpHat,
     RightHat,
     DownHat,
+
+    EmergencyStop,
 }
 
 unsafe impl Send for Controller {}
@@ -65,26 +134,30 @@ fn normalize(value: i32) -> f32 {
 }
 
 impl Controller {
-    fn all_event_devices() -> Vec<String> {
+    fn all_event_devices() -> Result<Vec<String>, ControllerError> {
         let mut devices = vec![];
-        for input in fs::read_dir(Path::new("/dev/input/")).unwrap() {
-            let input = input.unwrap();
-            let name = input.file_name().into_string().unwrap();
+        for input in fs::read_dir(Path::new("/dev/input/"))? {
+            let input = input?;
+            let path = input.path();
+            let is_event_device = input.file_name().to_str().map_or(false, |name| name.starts_with("event"));
 
-            if !input.path().is_dir() && name.starts_with("event") {
-                if let Ok(file) = fs::File::open(input.path()) {
-                    devices.push(String::from(input.path().to_str().unwrap()));
+            if !path.is_dir() && is_event_device {
+                if let (Ok(_), Some(path)) = (fs::File::open(&path), path.to_str()) {
+                    devices.push(String::from(path));
                 }
             }
         }
 
-        devices
+        Ok(devices)
     }
 
-    fn list_controller_paths() -> Vec<String> {
+    fn list_controller_paths() -> Result<Vec<String>, ControllerError> {
         let mut controllers = vec![];
-        for path in Self::all_event_devices() {
-            let file = fs::File::open(Path::new(&path)).unwrap();
+        for path in Self::all_event_devices()? {
+            let file = match fs::File::open(Path::new(&path)) {
+                Ok(file) => file,
+                Err(_) => continue
+            };
             if let Ok(device) = evdev::Device::new_from_fd(file) {
                 if let Some("Wireless Controller") = device.name() {
                     controllers.push(path);
@@ -92,18 +165,18 @@ impl Controller {
             }
         }
 
-        controllers
+        Ok(controllers)
     }
 
-    pub fn get_controller(index: usize) -> Option<Controller> {
-        let mut paths = Self::list_controller_paths();
+    pub fn get_controller(index: usize) -> Result<Controller, ControllerError> {
+        let mut paths = Self::list_controller_paths()?;
 
         if index >= paths.len() {
-            None
+            Err(ControllerError::NotFound(index))
         } else {
             let path = paths.remove(index);
-            let file = fs::File::open(Path::new(&path)).unwrap();
-            Some(Controller::new(evdev::Device::new_from_fd(file).unwrap()))
+            let file = fs::File::open(Path::new(&path))?;
+            Ok(Controller::new(evdev::Device::new_from_fd(file)?))
         }
     }
 
@@ -111,7 +184,8 @@ impl Controller {
         Controller {
             device,
             event_channel: None,
-            state: Arc::new(Mutex::new(State::new()))
+            state: Arc::new(Mutex::new(State::new())),
+            emergency_combination: EmergencyCombination::default()
         }
     }
 
@@ -123,13 +197,16 @@ impl Controller {
         self.state.clone()
     }
 
-    pub fn start(&mut self, running: Arc<AtomicBool>) {
+    pub fn start(&mut self, running: Arc<AtomicBool>) -> Result<(), ControllerError> {
         let mut timeout = TimeVal::milliseconds(100);
         let mut fdset = FdSet::new();
-        fdset.insert(self.device.fd().unwrap().into_raw_fd());
+        fdset.insert(self.device.fd().ok_or(ControllerError::NoFileDescriptor)?.into_raw_fd());
 
         while (*running).load(Ordering::Relaxed) {
-            select(None, Some(&mut fdset), None, None, Some(&mut timeout)).unwrap();
+            match select(None, Some(&mut fdset), None, None, Some(&mut timeout)) {
+                Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => (),
+                Err(e) => return Err(ControllerError::Select(e))
+            }
             match self.device.next_event(evdev::ReadFlag::NORMAL) {
                 Ok((evdev::ReadStatus::Success, event)) => {
                     match (&event.event_code, event.value) {
@@ -142,49 +219,62 @@ impl Controller {
                         _ => ()
                     }
 
-                    if let Some(channel) = &mut self.event_channel {
-                        match (&event.event_code, event.value) {
-                            (EventCode::EV_KEY(EV_KEY::BTN_SOUTH), 1) => channel.send(Event::XPress).unwrap(),
-                            (EventCode::EV_KEY(EV_KEY::BTN_WEST), 1) => channel.send(Event::SquarePress).unwrap(),
-                            (EventCode::EV_KEY(EV_KEY::BTN_NORTH), 1) => channel.send(Event::TrianglePress).unwrap(),
-                            (EventCode::EV_KEY(EV_KEY::BTN_EAST), 1) => channel.send(Event::CirclePress).unwrap(),
-                            
-                            (EventCode::EV_ABS(EV_ABS::ABS_HAT0X), -1) => channel.send(Event::LeftHat).unwrap(),
-                            (EventCode::EV_ABS(EV_ABS::ABS_HAT0X), 1) => channel.send(Event::RightHat).unwrap(),
-                            (EventCode::EV_ABS(EV_ABS::ABS_HAT0Y), 1) => channel.send(Event::DownHat).unwrap(),
-                            (EventCode::EV_ABS(EV_ABS::ABS_HAT0Y), -1) => channel.send(Event::UpHat).unwrap(),
-
-                            _ => ()
+                    let is_emergency = match &event.event_code {
+                        EventCode::EV_KEY(key) => self.emergency_combination.update(key, event.value),
+                        _ => false
+                    };
+
+                    let mapped_event = match (&event.event_code, event.value) {
+                        _ if is_emergency => Some(Event::EmergencyStop),
+
+                        (EventCode::EV_KEY(EV_KEY::BTN_SOUTH), 1) => Some(Event::XPress),
+                        (EventCode::EV_KEY(EV_KEY::BTN_WEST), 1) => Some(Event::SquarePress),
+                        (EventCode::EV_KEY(EV_KEY::BTN_NORTH), 1) => Some(Event::TrianglePress),
+                        (EventCode::EV_KEY(EV_KEY::BTN_EAST), 1) => Some(Event::CirclePress),
+                        (EventCode::EV_KEY(EV_KEY::BTN_START), 1) => Some(Event::OptionsPress),
+                        (EventCode::EV_KEY(EV_KEY::BTN_SELECT), 1) => Some(Event::SharePress),
+                        (EventCode::EV_KEY(EV_KEY::BTN_TR2), 1) => Some(Event::R2Press),
+                        (EventCode::EV_KEY(EV_KEY::BTN_TL2), 1) => Some(Event::L2Press),
+
+                        (EventCode::EV_ABS(EV_ABS::ABS_HAT0X), -1) => Some(Event::LeftHat),
+                        (EventCode::EV_ABS(EV_ABS::ABS_HAT0X), 1) => Some(Event::RightHat),
+                        (EventCode::EV_ABS(EV_ABS::ABS_HAT0Y), 1) => Some(Event::DownHat),
+                        (EventCode::EV_ABS(EV_ABS::ABS_HAT0Y), -1) => Some(Event::UpHat),
+
+                        _ => None
+                    };
+
+                    if let (Some(channel), Some(mapped_event)) = (&self.event_channel, mapped_event) {
+                        if channel.send(mapped_event).is_err() {
+                            // Nobody is listening for events anymore
+                            self.event_channel = None;
                         }
                     }
                 },
                 Ok(_) => (),
-                Err(_) => ()
+                // Nothing to read yet, an unplugged controller fails with ENODEV on every read instead
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(ControllerError::Read(e))
             }
         }
+
+        Ok(())
     }
 }
 
-fn main() {
-    let (sender, receiver) = channel();
-    let mut controller = Controller::get_controller(0).unwrap();
-    controller.set_event_listener(sender);
-    let state = controller.get_state();
-
-    let is_running = Arc::new(AtomicBool::new(true));
-    let thread_running = is_running.clone();
-    let thread = thread::spawn(move || {
-        controller.start(thread_running);
-    });
-
-    loop {
-        if let Ok(event) = receiver.recv_timeout(Duration::from_secs(1)) {
-            println!("Received event: {:?}", event);
-        } else {
-            println!("{:?}", state.lock().unwrap());
-        }
-    }
+#[test]
+fn test_emergency_combination() {
+    let mut combination = EmergencyCombination::default();
+    assert!(!combination.update(&EV_KEY::BTN_EAST, 1));
+    assert!(!combination.update(&EV_KEY::BTN_EAST, 0));
+
+    assert!(!combination.update(&EV_KEY::BTN_TL, 1));
+    assert!(!combination.update(&EV_KEY::BTN_TR, 1));
+    assert!(!combination.update(&EV_KEY::BTN_SOUTH, 1));
+    assert!(combination.update(&EV_KEY::BTN_EAST, 1));
 
-    is_running.store(false, Ordering::Relaxed);
-    thread.join().expect("Failed to join thread");
+    // Holding the buttons down does not repeat the stop, pressing one of them again does
+    assert!(!combination.update(&EV_KEY::BTN_EAST, 2));
+    assert!(!combination.update(&EV_KEY::BTN_TL, 0));
+    assert!(combination.update(&EV_KEY::BTN_TL, 1));
 }
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::error::Error;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+
+use crate::controller;
+use crate::player;
+use crate::protocol::FlipDirection;
+use crate::sdk::SdkError;
+use crate::tello::{ Telemetry, TelloError };
+
+#[derive(Debug)]
+pub enum DroneError {
+    Tello(TelloError),
+    Sdk(SdkError),
+}
+
+impl fmt::Display for DroneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DroneError::Tello(e) => write!(f, "{}", e),
+            DroneError::Sdk(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for DroneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DroneError::Tello(e) => Some(e),
+            DroneError::Sdk(e) => Some(e),
+        }
+    }
+}
+
+impl From<TelloError> for DroneError {
+    fn from(e: TelloError) -> DroneError {
+        DroneError::Tello(e)
+    }
+}
+
+impl From<SdkError> for DroneError {
+    fn from(e: SdkError) -> DroneError {
+        DroneError::Sdk(e)
+    }
+}
+
+// The commands shared by the binary protocol, the text SDK and the mock, so mission code
+// and UIs can be written once against any of them. Commands return once the drone has
+// accepted them, stick input is fire and forget and should be sent continuously.
+pub trait DroneControl {
+    fn takeoff(&self) -> Result<(), DroneError>;
+    fn land(&self) -> Result<(), DroneError>;
+    fn emergency(&self) -> Result<(), DroneError>;
+    fn flip(&self, direction: FlipDirection) -> Result<(), DroneError>;
+    fn set_sticks(&self, sticks: controller::State) -> Result<(), DroneError>;
+
+    fn set_telemetry_listener(&mut self, listener: Sender<Telemetry>) -> Result<(), DroneError>;
+    fn start_video(&mut self, frame_channel: Sender<player::Frame>) -> Result<(), DroneError>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MockCommand {
+    Takeoff,
+    Land,
+    Emergency,
+    Flip(FlipDirection),
+    Sticks(controller::State),
+}
+
+// In-memory drone recording every command it is given. Telemetry and video frames are
+// only delivered when pushed explicitly, which keeps tests of mission code deterministic.
+pub struct MockDrone {
+    commands: Mutex<Vec<MockCommand>>,
+    telemetry_listener: Mutex<Option<Sender<Telemetry>>>,
+    frame_channel: Mutex<Option<Sender<player::Frame>>>,
+}
+
+impl Default for MockDrone {
+    fn default() -> MockDrone {
+        MockDrone {
+            commands: Mutex::new(vec![]),
+            telemetry_listener: Mutex::new(None),
+            frame_channel: Mutex::new(None),
+        }
+    }
+}
+
+impl MockDrone {
+    pub fn new() -> MockDrone {
+        MockDrone::default()
+    }
+
+    pub fn commands(&self) -> Vec<MockCommand> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    // Returns whether anybody received the telemetry
+    pub fn publish(&self, telemetry: Telemetry) -> bool {
+        let mut listener = self.telemetry_listener.lock().unwrap();
+        let sent = match &*listener {
+            Some(listener) => listener.send(telemetry).is_ok(),
+            None => false
+        };
+        if !sent {
+            *listener = None;
+        }
+        sent
+    }
+
+    // Returns whether anybody received the frame
+    pub fn push_frame(&self, frame: player::Frame) -> bool {
+        let mut frame_channel = self.frame_channel.lock().unwrap();
+        let sent = match &*frame_channel {
+            Some(frame_channel) => frame_channel.send(frame).is_ok(),
+            None => false
+        };
+        if !sent {
+            *frame_channel = None;
+        }
+        sent
+    }
+
+    fn record(&self, command: MockCommand) -> Result<(), DroneError> {
+        self.commands.lock().unwrap().push(command);
+        Ok(())
+    }
+}
+
+impl DroneControl for MockDrone {
+    fn takeoff(&self) -> Result<(), DroneError> {
+        self.record(MockCommand::Takeoff)
+    }
+
+    fn land(&self) -> Result<(), DroneError> {
+        self.record(MockCommand::Land)
+    }
+
+    fn emergency(&self) -> Result<(), DroneError> {
+        self.record(MockCommand::Emergency)
+    }
+
+    fn flip(&self, direction: FlipDirection) -> Result<(), DroneError> {
+        self.record(MockCommand::Flip(direction))
+    }
+
+    fn set_sticks(&self, sticks: controller::State) -> Result<(), DroneError> {
+        self.record(MockCommand::Sticks(sticks))
+    }
+
+    fn set_telemetry_listener(&mut self, listener: Sender<Telemetry>) -> Result<(), DroneError> {
+        *self.telemetry_listener.lock().unwrap() = Some(listener);
+        Ok(())
+    }
+
+    fn start_video(&mut self, frame_channel: Sender<player::Frame>) -> Result<(), DroneError> {
+        *self.frame_channel.lock().unwrap() = Some(frame_channel);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn fly_forward_and_back<D: DroneControl>(drone: &D) -> Result<(), DroneError> {
+    // The left stick pitches, pushing it away flies forward
+    let forward = controller::State { joystick_left_x: 0.0, joystick_left_y: -0.5, joystick_right_x: 0.0, joystick_right_y: 0.0 };
+    drone.takeoff()?;
+    drone.set_sticks(forward)?;
+    drone.flip(FlipDirection::Backward)?;
+    drone.land()
+}
+
+#[test]
+fn test_mission_against_mock_drone() {
+    let drone = MockDrone::new();
+    fly_forward_and_back(&drone).unwrap();
+
+    let commands = drone.commands();
+    assert_eq!(commands.len(), 4);
+    assert_eq!(commands[0], MockCommand::Takeoff);
+    assert!(matches!(commands[1], MockCommand::Sticks(sticks) if sticks.joystick_left_y == -0.5));
+    assert_eq!(commands[2], MockCommand::Flip(FlipDirection::Backward));
+    assert_eq!(commands[3], MockCommand::Land);
+}
+
+#[test]
+fn test_mock_drone_subscriptions() {
+    use std::sync::mpsc::channel;
+
+    let mut drone = MockDrone::new();
+    assert!(!drone.publish(Telemetry::Disconnected));
+
+    let (telemetry_sender, telemetry_receiver) = channel();
+    let (frame_sender, frame_receiver) = channel();
+    drone.set_telemetry_listener(telemetry_sender).unwrap();
+    drone.start_video(frame_sender).unwrap();
+
+    assert!(drone.publish(Telemetry::Reconnected));
+    assert!(matches!(telemetry_receiver.try_recv(), Ok(Telemetry::Reconnected)));
+
+    assert!(drone.push_frame(player::Frame { width: 2, height: 1, data: vec![0; 8] }));
+    assert_eq!(frame_receiver.try_recv().unwrap().width, 2);
+
+    drop(frame_receiver);
+    assert!(!drone.push_frame(player::Frame { width: 2, height: 1, data: vec![0; 8] }));
+}
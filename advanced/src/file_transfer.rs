@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+
+// Files are sent in chunks of up to 1 KiB, and every 8 chunks form a piece which has to be
+// acknowledged before the drone moves on. Chunk numbers count from the start of the file.
+pub const CHUNK_SIZE: usize = 1024;
+pub const CHUNKS_PER_PIECE: u32 = 8;
+
+// Announces a file before its first chunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileInfo {
+    pub file_type: u8,
+    pub size: u32,
+    pub file_id: u16,
+}
+
+impl FileInfo {
+    pub const PAYLOAD_SIZE: usize = 7;
+
+    pub fn parse(bytes: &[u8]) -> Option<FileInfo> {
+        if bytes.len() < FileInfo::PAYLOAD_SIZE {
+            return None;
+        }
+
+        Some(FileInfo {
+            file_type: bytes[0],
+            size: u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+            file_id: u16::from_le_bytes([bytes[5], bytes[6]]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FileInfo::PAYLOAD_SIZE);
+        bytes.push(self.file_type);
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes.extend_from_slice(&self.file_id.to_le_bytes());
+        bytes
+    }
+
+    pub fn chunk_count(&self) -> u32 {
+        self.size.div_ceil(CHUNK_SIZE as u32)
+    }
+
+    pub fn piece_count(&self) -> u32 {
+        self.chunk_count().div_ceil(CHUNKS_PER_PIECE)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileChunk {
+    pub file_id: u16,
+    pub piece: u32,
+    pub chunk: u32,
+    pub data: Vec<u8>,
+}
+
+impl FileChunk {
+    pub const HEADER_SIZE: usize = 12;
+
+    pub fn parse(bytes: &[u8]) -> Option<FileChunk> {
+        if bytes.len() < FileChunk::HEADER_SIZE {
+            return None;
+        }
+
+        let length = u16::from_le_bytes([bytes[10], bytes[11]]) as usize;
+        let data = bytes.get(FileChunk::HEADER_SIZE..FileChunk::HEADER_SIZE + length)?;
+        Some(FileChunk {
+            file_id: u16::from_le_bytes([bytes[0], bytes[1]]),
+            piece: u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+            chunk: u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]),
+            data: data.to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FileChunk::HEADER_SIZE + self.data.len());
+        bytes.extend_from_slice(&self.file_id.to_le_bytes());
+        bytes.extend_from_slice(&self.piece.to_le_bytes());
+        bytes.extend_from_slice(&self.chunk.to_le_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+// Payload acknowledging a complete piece, the first byte marks the last piece of the file
+pub fn piece_ack(file_id: u16, piece: u32, is_last: bool) -> Vec<u8> {
+    let mut payload = vec![is_last as u8];
+    payload.extend_from_slice(&file_id.to_le_bytes());
+    payload.extend_from_slice(&piece.to_le_bytes());
+    payload
+}
+
+// Returns the file id and piece number of a piece ack
+pub fn parse_piece_ack(payload: &[u8]) -> Option<(u16, u32)> {
+    match payload {
+        &[_, f0, f1, p0, p1, p2, p3, ..] => Some((u16::from_le_bytes([f0, f1]), u32::from_le_bytes([p0, p1, p2, p3]))),
+        _ => None
+    }
+}
+
+// Payload telling the drone the whole file has arrived
+pub fn file_done(file_id: u16, size: u32) -> Vec<u8> {
+    let mut payload = file_id.to_le_bytes().to_vec();
+    payload.extend_from_slice(&size.to_le_bytes());
+    payload
+}
+
+// Collects the chunks of a file in whatever order they arrive
+pub struct FileAssembler {
+    info: FileInfo,
+    chunks: BTreeMap<u32, Vec<u8>>,
+}
+
+impl FileAssembler {
+    pub fn new(info: FileInfo) -> FileAssembler {
+        FileAssembler {
+            info,
+            chunks: BTreeMap::new(),
+        }
+    }
+
+    pub fn info(&self) -> &FileInfo {
+        &self.info
+    }
+
+    pub fn received_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // Returns whether the chunk is new, chunks of other files and repeated chunks are ignored
+    pub fn add(&mut self, chunk: FileChunk) -> bool {
+        if chunk.file_id != self.info.file_id || chunk.chunk >= self.info.chunk_count() {
+            return false;
+        }
+
+        self.chunks.insert(chunk.chunk, chunk.data).is_none()
+    }
+
+    pub fn is_piece_complete(&self, piece: u32) -> bool {
+        let first = piece * CHUNKS_PER_PIECE;
+        let last = ((piece + 1) * CHUNKS_PER_PIECE).min(self.info.chunk_count());
+        first < last && (first..last).all(|chunk| self.chunks.contains_key(&chunk))
+    }
+
+    // Every piece before this one is complete, so it is the one to ask for again when the transfer stalls
+    pub fn first_incomplete_piece(&self) -> Option<u32> {
+        (0..self.info.piece_count()).find(|&piece| !self.is_piece_complete(piece))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.first_incomplete_piece().is_none()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.chunks.values().flatten().cloned().collect();
+        bytes.truncate(self.info.size as usize);
+        bytes
+    }
+}
+
+#[cfg(test)]
+fn test_file(size: usize) -> (FileInfo, Vec<FileChunk>, Vec<u8>) {
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    let info = FileInfo { file_type: 1, size: size as u32, file_id: 7 };
+    let chunks = data.chunks(CHUNK_SIZE).enumerate().map(|(chunk, data)| FileChunk {
+        file_id: 7,
+        piece: chunk as u32 / CHUNKS_PER_PIECE,
+        chunk: chunk as u32,
+        data: data.to_vec()
+    }).collect();
+    (info, chunks, data)
+}
+
+#[test]
+fn test_file_transfer_round_trip() {
+    let (info, chunks, _) = test_file(3000);
+    assert_eq!(FileInfo::parse(&info.to_bytes()), Some(info));
+    assert_eq!(FileInfo::parse(&info.to_bytes()[..6]), None);
+    assert_eq!((info.chunk_count(), info.piece_count()), (3, 1));
+
+    let bytes = chunks[2].to_bytes();
+    assert_eq!(bytes.len(), FileChunk::HEADER_SIZE + 3000 - 2 * CHUNK_SIZE);
+    assert_eq!(FileChunk::parse(&bytes), Some(chunks[2].clone()));
+    assert_eq!(FileChunk::parse(&bytes[..bytes.len() - 1]), None);
+
+    assert_eq!(piece_ack(7, 2, true), vec![1, 7, 0, 2, 0, 0, 0]);
+    assert_eq!(parse_piece_ack(&piece_ack(7, 2, true)), Some((7, 2)));
+    assert_eq!(parse_piece_ack(&[0, 7, 0]), None);
+    assert_eq!(file_done(info.file_id, info.size), vec![7, 0, 0xb8, 0x0b, 0, 0]);
+}
+
+#[test]
+fn test_assemble_out_of_order_chunks() {
+    let (info, chunks, data) = test_file(CHUNK_SIZE * 17 + 5);
+    assert_eq!(info.piece_count(), 3);
+
+    let mut assembler = FileAssembler::new(info);
+    assert_eq!(assembler.first_incomplete_piece(), Some(0));
+
+    // The last piece arrives first, and a chunk of the first piece goes missing
+    for chunk in chunks.iter().rev().filter(|chunk| chunk.chunk != 3) {
+        assert!(assembler.add(chunk.clone()));
+    }
+    assert!(assembler.is_piece_complete(2));
+    assert!(assembler.is_piece_complete(1));
+    assert!(!assembler.is_piece_complete(0));
+    assert_eq!(assembler.first_incomplete_piece(), Some(0));
+
+    assert!(!assembler.add(FileChunk { file_id: 8, ..chunks[3].clone() }));
+    assert!(!assembler.add(FileChunk { chunk: 18, ..chunks[3].clone() }));
+    assert!(assembler.add(chunks[3].clone()));
+    assert!(!assembler.add(chunks[3].clone()));
+    assert!(assembler.is_complete());
+
+    assert_eq!(assembler.received_chunks(), 18);
+    assert_eq!(assembler.to_bytes(), data);
+}
@@ -0,0 +1,173 @@
+use bitflags::bitflags;
+
+bitflags! {
+    pub struct SensorState: u8 {
+        const IMU = 0x01;
+        const PRESSURE = 0x02;
+        const DOWN_VISUAL = 0x04;
+        const POWER = 0x08;
+        const BATTERY = 0x10;
+        const GRAVITY = 0x20;
+        const WIND = 0x80;
+    }
+}
+
+bitflags! {
+    pub struct FlightState: u8 {
+        const EM_SKY = 0x01;
+        const EM_GROUND = 0x02;
+        const EM_OPEN = 0x04;
+        const DRONE_HOVER = 0x08;
+        const OUTAGE_RECORDING = 0x10;
+        const BATTERY_LOW = 0x20;
+        const BATTERY_LOWER = 0x40;
+        const FACTORY_MODE = 0x80;
+    }
+}
+
+bitflags! {
+    pub struct FrontState: u8 {
+        const FRONT_IN = 0x01;
+        const FRONT_OUT = 0x02;
+        const FRONT_LSC = 0x04;
+    }
+}
+
+// Payload of the 0x56 flight data message. Heights are in decimeters and speeds in
+// decimeters per second, newer firmware appends fields we do not know about yet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlightData {
+    pub height: i16,
+    pub north_speed: i16,
+    pub east_speed: i16,
+    pub ground_speed: i16,
+    pub fly_time: i16,
+
+    pub sensors: SensorState,
+    pub imu_calibration_state: u8,
+
+    pub battery_percentage: u8,
+    pub drone_fly_time_left: i16,
+    pub drone_battery_left: i16,
+
+    pub flight_state: FlightState,
+    pub fly_mode: u8,
+    pub throw_fly_timer: u8,
+    pub camera_state: u8,
+    pub electrical_machinery_state: u8,
+
+    pub front: FrontState,
+    pub temperature_height: bool
+}
+
+impl FlightData {
+    pub const PAYLOAD_SIZE: usize = 24;
+
+    pub fn parse(bytes: &[u8]) -> Option<FlightData> {
+        if bytes.len() < FlightData::PAYLOAD_SIZE {
+            return None;
+        }
+
+        let int16 = |offset: usize| i16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+
+        Some(FlightData {
+            height: int16(0),
+            north_speed: int16(2),
+            east_speed: int16(4),
+            ground_speed: int16(6),
+            fly_time: int16(8),
+
+            sensors: SensorState::from_bits_truncate(bytes[10]),
+            imu_calibration_state: bytes[11],
+
+            battery_percentage: bytes[12],
+            drone_fly_time_left: int16(13),
+            drone_battery_left: int16(15),
+
+            flight_state: FlightState::from_bits_truncate(bytes[17]),
+            fly_mode: bytes[18],
+            throw_fly_timer: bytes[19],
+            camera_state: bytes[20],
+            electrical_machinery_state: bytes[21],
+
+            front: FrontState::from_bits_truncate(bytes[22]),
+            temperature_height: (bytes[23] & 0x1) != 0
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FlightData::PAYLOAD_SIZE);
+        for value in &[self.height, self.north_speed, self.east_speed, self.ground_speed, self.fly_time] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.push(self.sensors.bits());
+        bytes.push(self.imu_calibration_state);
+        bytes.push(self.battery_percentage);
+        bytes.extend_from_slice(&self.drone_fly_time_left.to_le_bytes());
+        bytes.extend_from_slice(&self.drone_battery_left.to_le_bytes());
+        bytes.push(self.flight_state.bits());
+        bytes.push(self.fly_mode);
+        bytes.push(self.throw_fly_timer);
+        bytes.push(self.camera_state);
+        bytes.push(self.electrical_machinery_state);
+        bytes.push(self.front.bits());
+        bytes.push(self.temperature_height as u8);
+        bytes
+    }
+
+    pub fn is_flying(&self) -> bool {
+        self.flight_state.contains(FlightState::EM_SKY)
+    }
+}
+
+#[test]
+fn test_parse_captured_flight_data() {
+    let payload = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 81, 0, 0, 252, 15, 0, 6, 0, 0, 0, 0, 0];
+    let data = FlightData::parse(&payload).unwrap();
+
+    assert_eq!(data.height, 0);
+    assert_eq!(data.battery_percentage, 81);
+    assert_eq!(data.drone_battery_left, 4092);
+    assert_eq!(data.fly_mode, 6);
+    assert_eq!(data.flight_state, FlightState::empty());
+    assert!(!data.is_flying());
+}
+
+#[test]
+fn test_parse_all_flight_data_fields() {
+    let payload = [
+        0x0c, 0x00, 0xfe, 0xff, 0x03, 0x00, 0x01, 0x00, 0x2a, 0x00,
+        0x81, 0x02, 0x4b, 0x10, 0x01, 0xa0, 0x0f,
+        0x29, 11, 5, 1, 2, 0x05, 0x01
+    ];
+    let data = FlightData::parse(&payload).unwrap();
+
+    assert_eq!(data.height, 12);
+    assert_eq!(data.north_speed, -2);
+    assert_eq!(data.east_speed, 3);
+    assert_eq!(data.ground_speed, 1);
+    assert_eq!(data.fly_time, 42);
+    assert_eq!(data.sensors, SensorState::IMU | SensorState::WIND);
+    assert_eq!(data.imu_calibration_state, 2);
+    assert_eq!(data.battery_percentage, 75);
+    assert_eq!(data.drone_fly_time_left, 0x110);
+    assert_eq!(data.drone_battery_left, 4000);
+    assert_eq!(data.flight_state, FlightState::EM_SKY | FlightState::DRONE_HOVER | FlightState::BATTERY_LOW);
+    assert_eq!(data.fly_mode, 11);
+    assert_eq!(data.throw_fly_timer, 5);
+    assert_eq!(data.camera_state, 1);
+    assert_eq!(data.electrical_machinery_state, 2);
+    assert_eq!(data.front, FrontState::FRONT_IN | FrontState::FRONT_LSC);
+    assert!(data.temperature_height);
+    assert!(data.is_flying());
+
+    assert_eq!(data.to_bytes(), payload.to_vec());
+}
+
+#[test]
+fn test_parse_flight_data_length() {
+    let payload = [0u8; 32];
+    assert!(FlightData::parse(&payload).is_some());
+    assert!(FlightData::parse(&payload[..FlightData::PAYLOAD_SIZE]).is_some());
+    assert!(FlightData::parse(&payload[..FlightData::PAYLOAD_SIZE - 1]).is_none());
+}
@@ -0,0 +1,17 @@
+extern crate gstreamer as gst;
+extern crate gstreamer_app as gst_app;
+extern crate gstreamer_video as gst_video;
+
+mod crc;
+pub mod protocol;
+pub mod flight_data;
+pub mod log_data;
+pub mod file_transfer;
+pub mod video;
+pub mod video_capture;
+pub mod tello;
+pub mod sdk;
+pub mod sdk_state;
+pub mod controller;
+pub mod drone;
+pub mod player;
@@ -0,0 +1,338 @@
+const RECORD_SEPARATOR: u8 = 0x55;
+const RECORD_HEADER_SIZE: usize = 10;
+const RECORD_CRC_SIZE: usize = 2;
+
+const MVO_RECORD_ID: u16 = 0x001d;
+const IMU_RECORD_ID: u16 = 0x0800;
+
+const MVO_VALID_VELOCITY_X: u8 = 0x01;
+const MVO_VALID_VELOCITY_Y: u8 = 0x02;
+const MVO_VALID_VELOCITY_Z: u8 = 0x04;
+const MVO_VALID_POSITION_X: u8 = 0x10;
+const MVO_VALID_POSITION_Y: u8 = 0x20;
+const MVO_VALID_POSITION_Z: u8 = 0x40;
+
+// Visual odometry estimate. Fields are None when the drone flags them as invalid,
+// which happens whenever the downward camera loses track of the ground.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MvoRecord {
+    pub velocity_x: Option<i16>,
+    pub velocity_y: Option<i16>,
+    pub velocity_z: Option<i16>,
+    pub position_x: Option<f32>,
+    pub position_y: Option<f32>,
+    pub position_z: Option<f32>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuRecord {
+    pub acceleration: [f32; 3],
+    pub gyro: [f32; 3],
+    pub quaternion: [f32; 4],
+    pub velocity: [f32; 3],
+    pub temperature: f32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogRecord {
+    Mvo(MvoRecord),
+    Imu(ImuRecord)
+}
+
+fn read_i16(bytes: &[u8], offset: usize) -> i16 {
+    i16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_f32s(bytes: &[u8], offset: usize, values: &mut [f32]) {
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = read_f32(bytes, offset + 4 * i);
+    }
+}
+
+fn write_f32s(bytes: &mut [u8], offset: usize, values: &[f32]) {
+    for (i, value) in values.iter().enumerate() {
+        bytes[offset + 4 * i..offset + 4 * (i + 1)].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+impl MvoRecord {
+    const BODY_SIZE: usize = 77;
+
+    fn parse(body: &[u8]) -> Option<MvoRecord> {
+        if body.len() < MvoRecord::BODY_SIZE {
+            return None;
+        }
+
+        let flags = body[76];
+        let valid = |flag: u8| (flags & flag) != 0;
+
+        Some(MvoRecord {
+            velocity_x: if valid(MVO_VALID_VELOCITY_X) { Some(read_i16(body, 2)) } else { None },
+            velocity_y: if valid(MVO_VALID_VELOCITY_Y) { Some(read_i16(body, 4)) } else { None },
+            velocity_z: if valid(MVO_VALID_VELOCITY_Z) { Some(read_i16(body, 6)) } else { None },
+            position_x: if valid(MVO_VALID_POSITION_X) { Some(read_f32(body, 8)) } else { None },
+            position_y: if valid(MVO_VALID_POSITION_Y) { Some(read_f32(body, 12)) } else { None },
+            position_z: if valid(MVO_VALID_POSITION_Z) { Some(read_f32(body, 16)) } else { None }
+        })
+    }
+
+    fn to_body(&self) -> Vec<u8> {
+        let mut body = vec![0u8; MvoRecord::BODY_SIZE];
+        let mut flags = 0;
+
+        let velocities = [
+            (self.velocity_x, 2, MVO_VALID_VELOCITY_X),
+            (self.velocity_y, 4, MVO_VALID_VELOCITY_Y),
+            (self.velocity_z, 6, MVO_VALID_VELOCITY_Z)
+        ];
+        for (velocity, offset, flag) in velocities.iter() {
+            if let Some(velocity) = velocity {
+                body[*offset..*offset + 2].copy_from_slice(&velocity.to_le_bytes());
+                flags |= flag;
+            }
+        }
+
+        let positions = [
+            (self.position_x, 8, MVO_VALID_POSITION_X),
+            (self.position_y, 12, MVO_VALID_POSITION_Y),
+            (self.position_z, 16, MVO_VALID_POSITION_Z)
+        ];
+        for (position, offset, flag) in positions.iter() {
+            if let Some(position) = position {
+                write_f32s(&mut body, *offset, &[*position]);
+                flags |= flag;
+            }
+        }
+
+        body[76] = flags;
+        body
+    }
+}
+
+impl ImuRecord {
+    const BODY_SIZE: usize = 108;
+
+    fn parse(body: &[u8]) -> Option<ImuRecord> {
+        if body.len() < ImuRecord::BODY_SIZE {
+            return None;
+        }
+
+        let mut record = ImuRecord {
+            acceleration: [0.0; 3],
+            gyro: [0.0; 3],
+            quaternion: [0.0; 4],
+            velocity: [0.0; 3],
+            temperature: (read_i16(body, 106) as f32) / 100.0
+        };
+        read_f32s(body, 20, &mut record.acceleration);
+        read_f32s(body, 32, &mut record.gyro);
+        read_f32s(body, 48, &mut record.quaternion);
+        read_f32s(body, 76, &mut record.velocity);
+
+        Some(record)
+    }
+
+    fn to_body(&self) -> Vec<u8> {
+        let mut body = vec![0u8; ImuRecord::BODY_SIZE];
+        write_f32s(&mut body, 20, &self.acceleration);
+        write_f32s(&mut body, 32, &self.gyro);
+        write_f32s(&mut body, 48, &self.quaternion);
+        write_f32s(&mut body, 76, &self.velocity);
+        body[106..108].copy_from_slice(&((self.temperature * 100.0) as i16).to_le_bytes());
+        body
+    }
+
+    // Returns (pitch, roll, yaw) in degrees from the (w, x, y, z) attitude quaternion
+    pub fn euler_angles(&self) -> (f32, f32, f32) {
+        let [w, x, y, z] = self.quaternion;
+
+        let sin_pitch = (2.0 * (w * y - z * x)).max(-1.0).min(1.0);
+        let pitch = sin_pitch.asin();
+        let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+        let yaw = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+
+        (pitch.to_degrees(), roll.to_degrees(), yaw.to_degrees())
+    }
+}
+
+// Walks the records of a 0x1051 log data payload. Each record is laid out as
+//   0x55 | length (u16) | crc8 | id (u16) | xor key | 3 unknown bytes | body | crc16
+// with the body xor'ed with the key. Unknown record types are skipped, and parsing stops
+// at the first record that does not fit in the payload.
+pub fn parse(payload: &[u8]) -> Vec<LogRecord> {
+    let mut records = vec![];
+
+    let mut pos = 1;
+    while pos + RECORD_HEADER_SIZE + RECORD_CRC_SIZE <= payload.len() {
+        if payload[pos] != RECORD_SEPARATOR {
+            break;
+        }
+
+        let length = u16::from_le_bytes([payload[pos + 1], payload[pos + 2]]) as usize;
+        if length < RECORD_HEADER_SIZE + RECORD_CRC_SIZE || pos + length > payload.len() {
+            break;
+        }
+
+        let id = u16::from_le_bytes([payload[pos + 4], payload[pos + 5]]);
+        let xor_key = payload[pos + 6];
+        let body: Vec<u8> = payload[pos + RECORD_HEADER_SIZE..pos + length - RECORD_CRC_SIZE]
+            .iter()
+            .map(|byte| byte ^ xor_key)
+            .collect();
+
+        let record = match id {
+            MVO_RECORD_ID => MvoRecord::parse(&body).map(LogRecord::Mvo),
+            IMU_RECORD_ID => ImuRecord::parse(&body).map(LogRecord::Imu),
+            _ => None
+        };
+        if let Some(record) = record {
+            records.push(record);
+        }
+
+        pos += length;
+    }
+
+    records
+}
+
+fn encode_record(id: u16, xor_key: u8, body: &[u8]) -> Vec<u8> {
+    let length = (RECORD_HEADER_SIZE + body.len() + RECORD_CRC_SIZE) as u16;
+
+    let mut record = vec![RECORD_SEPARATOR];
+    record.extend_from_slice(&length.to_le_bytes());
+    record.push(0);
+    record.extend_from_slice(&id.to_le_bytes());
+    record.extend_from_slice(&[xor_key, 0, 0, 0]);
+    record.extend(body.iter().map(|byte| byte ^ xor_key));
+    record.extend_from_slice(&[0, 0]);
+    record
+}
+
+// Inverse of parse, used by the simulator. Record checksums are left as zero since
+// nothing on our side validates them.
+pub fn encode(records: &[LogRecord], xor_key: u8) -> Vec<u8> {
+    let mut payload = vec![0];
+    for record in records {
+        match record {
+            LogRecord::Mvo(mvo) => payload.extend(encode_record(MVO_RECORD_ID, xor_key, &mvo.to_body())),
+            LogRecord::Imu(imu) => payload.extend(encode_record(IMU_RECORD_ID, xor_key, &imu.to_body()))
+        }
+    }
+    payload
+}
+
+#[cfg(test)]
+fn mvo_body() -> Vec<u8> {
+    let mut body = vec![0u8; MvoRecord::BODY_SIZE];
+    body[2..4].copy_from_slice(&12i16.to_le_bytes());
+    body[4..6].copy_from_slice(&(-7i16).to_le_bytes());
+    body[6..8].copy_from_slice(&3i16.to_le_bytes());
+    body[8..12].copy_from_slice(&1.5f32.to_le_bytes());
+    body[12..16].copy_from_slice(&(-0.25f32).to_le_bytes());
+    body[16..20].copy_from_slice(&(-1.0f32).to_le_bytes());
+    body[76] = MVO_VALID_VELOCITY_X | MVO_VALID_VELOCITY_Y | MVO_VALID_POSITION_X | MVO_VALID_POSITION_Z;
+    body
+}
+
+#[cfg(test)]
+fn imu_body() -> Vec<u8> {
+    let mut body = vec![0u8; ImuRecord::BODY_SIZE];
+    let floats: [(usize, f32); 13] = [
+        (20, 0.1), (24, 0.2), (28, -1.0),
+        (32, 0.01), (36, 0.02), (40, 0.03),
+        (48, 0.70710677), (52, 0.0), (56, 0.0), (60, 0.70710677),
+        (76, 4.0), (80, 5.0), (84, 6.0)
+    ];
+    for (offset, value) in floats.iter() {
+        body[*offset..*offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    body[106..108].copy_from_slice(&4250i16.to_le_bytes());
+    body
+}
+
+#[test]
+fn test_parse_mvo_and_imu_records() {
+    let mut payload = vec![0];
+    payload.extend(encode_record(MVO_RECORD_ID, 0x5a, &mvo_body()));
+    payload.extend(encode_record(0x1234, 0x11, &[1, 2, 3, 4]));
+    payload.extend(encode_record(IMU_RECORD_ID, 0xa5, &imu_body()));
+
+    let records = parse(&payload);
+    assert_eq!(records.len(), 2);
+
+    assert_eq!(records[0], LogRecord::Mvo(MvoRecord {
+        velocity_x: Some(12),
+        velocity_y: Some(-7),
+        velocity_z: None,
+        position_x: Some(1.5),
+        position_y: None,
+        position_z: Some(-1.0)
+    }));
+
+    match records[1] {
+        LogRecord::Imu(imu) => {
+            assert_eq!(imu.acceleration, [0.1, 0.2, -1.0]);
+            assert_eq!(imu.gyro, [0.01, 0.02, 0.03]);
+            assert_eq!(imu.quaternion, [0.70710677, 0.0, 0.0, 0.70710677]);
+            assert_eq!(imu.velocity, [4.0, 5.0, 6.0]);
+            assert_eq!(imu.temperature, 42.5);
+
+            let (pitch, roll, yaw) = imu.euler_angles();
+            assert!(pitch.abs() < 0.01);
+            assert!(roll.abs() < 0.01);
+            assert!((yaw - 90.0).abs() < 0.01);
+        },
+        other => panic!("Expected IMU record, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_parse_truncated_log_data() {
+    let mut payload = vec![0];
+    payload.extend(encode_record(MVO_RECORD_ID, 0, &mvo_body()));
+    let imu_record = encode_record(IMU_RECORD_ID, 0, &imu_body());
+    payload.extend_from_slice(&imu_record[..imu_record.len() - 1]);
+
+    let mvo_end = 1 + RECORD_HEADER_SIZE + MvoRecord::BODY_SIZE + RECORD_CRC_SIZE;
+    for length in 0..mvo_end {
+        assert!(parse(&payload[..length]).is_empty());
+    }
+    assert_eq!(parse(&payload[..mvo_end]).len(), 1);
+    assert_eq!(parse(&payload).len(), 1);
+}
+
+#[test]
+fn test_parse_log_data_stops_at_bad_separator() {
+    let mut payload = vec![0];
+    payload.extend(encode_record(MVO_RECORD_ID, 0, &mvo_body()));
+    payload.extend(encode_record(MVO_RECORD_ID, 0, &mvo_body()));
+    let second_record = 1 + RECORD_HEADER_SIZE + MvoRecord::BODY_SIZE + RECORD_CRC_SIZE;
+    payload[second_record] = 0x54;
+
+    assert_eq!(parse(&payload).len(), 1);
+}
+
+#[test]
+fn test_parse_short_record_bodies() {
+    let mut payload = vec![0];
+    payload.extend(encode_record(MVO_RECORD_ID, 0, &mvo_body()[..MvoRecord::BODY_SIZE - 1]));
+    payload.extend(encode_record(IMU_RECORD_ID, 0, &imu_body()[..ImuRecord::BODY_SIZE - 1]));
+
+    assert!(parse(&payload).is_empty());
+}
+
+#[test]
+fn test_encode_log_data_round_trip() {
+    let records = parse(&[
+        vec![0],
+        encode_record(MVO_RECORD_ID, 0, &mvo_body()),
+        encode_record(IMU_RECORD_ID, 0, &imu_body())
+    ].concat());
+    assert_eq!(records.len(), 2);
+
+    assert_eq!(parse(&encode(&records, 0x3c)), records);
+}
@@ -1,13 +1,13 @@
 use vulkano_win::VkSurfaceBuild;
 use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, BufferAccess};
-use vulkano::instance::{ Instance, PhysicalDevice, QueueFamily };
-use vulkano::device::{ Device, DeviceExtensions };
+use vulkano::instance::{ Instance, InstanceCreationError, PhysicalDevice, QueueFamily };
+use vulkano::device::{ Device, DeviceCreationError, DeviceExtensions };
 use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use vulkano::format::Format;
 use vulkano::image::{ Dimensions, ImageUsage, SwapchainImage, StorageImage };
 use vulkano::sampler::{ Sampler, Filter, MipmapMode, SamplerAddressMode, BorderColor };
 use vulkano::swapchain;
-use vulkano::swapchain::{ AcquireError, Swapchain, SurfaceTransform, CompositeAlpha, PresentMode, FullscreenExclusive, ColorSpace, SwapchainCreationError };
+use vulkano::swapchain::{ AcquireError, CapabilitiesError, Swapchain, SurfaceTransform, CompositeAlpha, PresentMode, FullscreenExclusive, ColorSpace, SwapchainCreationError };
 use vulkano::pipeline::GraphicsPipeline;
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::framebuffer::{ Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass };
@@ -17,13 +17,10 @@ use vulkano::sync::{ FlushError, GpuFuture };
 use winit::window::{ Window, WindowBuilder };
 use winit::event_loop::{ EventLoop, ControlFlow };
 use winit::event::{ Event, WindowEvent };
-use std::sync::Arc;
-use png;
-use std::io::Cursor;
-use std::sync::mpsc::{ channel, Receiver };
-use std::thread;
-use std::sync::atomic::{ AtomicBool, Ordering };
-use std::time;
+use std::sync::{ Arc, Mutex };
+use std::fmt;
+use std::error::Error;
+use std::sync::mpsc::Receiver;
 
 pub struct Frame {
     pub width: u32,
@@ -31,8 +28,61 @@ pub struct Frame {
     pub data: Vec<u8>
 }
 
+const WINDOW_TITLE: &str = "Tello";
+
+type ExitHandler = Box<dyn FnOnce() + Send>;
+
 pub struct Player {
-    receiver: Receiver<Frame>
+    receiver: Receiver<Frame>,
+    status_receiver: Option<Receiver<String>>,
+    exit_handler: Option<ExitHandler>,
+}
+
+#[derive(Debug)]
+pub enum PlayerError {
+    Instance(InstanceCreationError),
+    NoPhysicalDevice,
+    Window(vulkano_win::CreationError),
+    NoQueueFamily,
+    Device(DeviceCreationError),
+    Capabilities(CapabilitiesError),
+    UnsupportedFormat(Format),
+    Swapchain(SwapchainCreationError),
+    Graphics(&'static str, Box<dyn Error>),
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlayerError::Instance(e) => write!(f, "Failed to initialize vulkano instance: {}", e),
+            PlayerError::NoPhysicalDevice => write!(f, "No physical device available"),
+            PlayerError::Window(e) => write!(f, "Failed to create window: {}", e),
+            PlayerError::NoQueueFamily => write!(f, "No queue family supports drawing to the window"),
+            PlayerError::Device(e) => write!(f, "Failed to create device: {}", e),
+            PlayerError::Capabilities(e) => write!(f, "Failed to query surface capabilities: {}", e),
+            PlayerError::UnsupportedFormat(format) => write!(f, "Unsupported swapchain format {:?}", format),
+            PlayerError::Swapchain(e) => write!(f, "Failed to create swapchain: {}", e),
+            PlayerError::Graphics(what, e) => write!(f, "Failed to create {}: {}", what, e),
+        }
+    }
+}
+
+impl Error for PlayerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PlayerError::Instance(e) => Some(e),
+            PlayerError::Window(e) => Some(e),
+            PlayerError::Device(e) => Some(e),
+            PlayerError::Capabilities(e) => Some(e),
+            PlayerError::Swapchain(e) => Some(e),
+            PlayerError::Graphics(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+fn graphics_error<E: Error + 'static>(what: &'static str) -> impl FnOnce(E) -> PlayerError {
+    move |e| PlayerError::Graphics(what, Box::new(e))
 }
 
 mod vs {
@@ -92,7 +142,7 @@ fn window_size_dependent_setup(
     images: &[Arc<SwapchainImage<Window>>],
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
     dynamic_state: &mut DynamicState,
-) -> Vec<Arc<dyn FramebufferAbstract + Send + Sync>> {
+) -> Result<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>, PlayerError> {
     let dimensions = images[0].dimensions();
 
     let viewport = Viewport {
@@ -105,19 +155,17 @@ fn window_size_dependent_setup(
     images
         .iter()
         .map(|image| {
-            Arc::new(
-                Framebuffer::start(render_pass.clone())
-                    .add(image.clone())
-                    .unwrap()
-                    .build()
-                    .unwrap(),
-            ) as Arc<dyn FramebufferAbstract + Send + Sync>
+            let framebuffer = Framebuffer::start(render_pass.clone())
+                .add(image.clone())
+                .and_then(|framebuffer| framebuffer.build())
+                .map_err(graphics_error("framebuffer"))?;
+            Ok(Arc::new(framebuffer) as Arc<dyn FramebufferAbstract + Send + Sync>)
         })
-        .collect::<Vec<_>>()
+        .collect()
 }
 
 fn alloc_video_frame_buffers(device: Arc<Device>, queue_family: QueueFamily, width: u32, height: u32)
-    -> (Arc<StorageImage<Format>>, Arc<CpuAccessibleBuffer<[u8]>>)
+    -> Result<(Arc<StorageImage<Format>>, Arc<CpuAccessibleBuffer<[u8]>>), PlayerError>
 {
     let dimensions = Dimensions::Dim2d {
         width: width,
@@ -128,30 +176,52 @@ fn alloc_video_frame_buffers(device: Arc<Device>, queue_family: QueueFamily, wid
         dimensions,
         Format::R8G8B8A8Unorm,
         Some(queue_family)
-    ).unwrap();
+    ).map_err(graphics_error("frame image"))?;
 
     let texture_buffer = CpuAccessibleBuffer::<[u8]>::from_iter(
         device.clone(),
         BufferUsage::transfer_source(),
         false,
         (0..width * height * 4).map(|_| 0u8)
-    ).unwrap();
+    ).map_err(graphics_error("texture buffer"))?;
 
-    (frame_image, texture_buffer)
+    Ok((frame_image, texture_buffer))
+}
+
+fn run_exit_handler(exit_handler: &Mutex<Option<ExitHandler>>) {
+    if let Some(exit_handler) = exit_handler.lock().unwrap().take() {
+        exit_handler();
+    }
 }
 
 impl Player {
     pub fn new(receiver: Receiver<Frame>) -> Player {
-        Player { receiver }
+        Player { receiver, status_receiver: None, exit_handler: None }
     }
 
-    pub fn run(self) {
+    // The latest status, e.g. the speed mode, is shown in the window title next to the video
+    pub fn set_status_receiver(&mut self, status_receiver: Receiver<String>) {
+        self.status_receiver = Some(status_receiver);
+    }
+
+    // Runs before the process exits, since the event loop never hands back control
+    pub fn set_exit_handler<F: FnOnce() + Send + 'static>(&mut self, exit_handler: F) {
+        self.exit_handler = Some(Box::new(exit_handler));
+    }
+
+    // Only returns if the player could not be set up, otherwise the event loop takes over the
+    // thread and exits the process once the window is closed. The exit handler runs either way.
+    pub fn run(mut self) -> Result<(), PlayerError> {
+        let exit_handler = Arc::new(Mutex::new(self.exit_handler.take()));
+        let result = self.run_event_loop(exit_handler.clone());
+        run_exit_handler(&exit_handler);
+        result
+    }
+
+    fn run_event_loop(self, exit_handler: Arc<Mutex<Option<ExitHandler>>>) -> Result<(), PlayerError> {
         let instance = {
             let extensions = vulkano_win::required_extensions();
-            match Instance::new(None, &extensions, None) {
-                Ok(inst) => inst,
-                Err(e) => panic!("Failed to initialize vulkano instance: {:?}", e)
-            }
+            Instance::new(None, &extensions, None).map_err(PlayerError::Instance)?
         };
 
         println!("Available physical devices:");
@@ -160,15 +230,15 @@ impl Player {
         }
         println!("");
 
-        let physical = PhysicalDevice::enumerate(&instance).next().unwrap();
+        let physical = PhysicalDevice::enumerate(&instance).next().ok_or(PlayerError::NoPhysicalDevice)?;
         println!("Using device: {} (type: {:?})", physical.name(), physical.ty());
 
         let event_loop = EventLoop::new();
-        let surface = WindowBuilder::new().build_vk_surface(&event_loop, instance.clone()).unwrap();
+        let surface = WindowBuilder::new().with_title(WINDOW_TITLE).build_vk_surface(&event_loop, instance.clone()).map_err(PlayerError::Window)?;
 
         let queue_family = physical.queue_families().find(|&q| {
             q.supports_graphics() && surface.is_supported(q).unwrap_or(false)
-        }).unwrap();
+        }).ok_or(PlayerError::NoQueueFamily)?;
 
         let device_ext = DeviceExtensions {
             khr_swapchain: true,
@@ -179,12 +249,12 @@ impl Player {
             physical.supported_features(),
             &device_ext,
             [(queue_family, 0.5)].iter().cloned()
-        ).unwrap();
+        ).map_err(PlayerError::Device)?;
 
-        let queue = queues.next().unwrap();
+        let queue = queues.next().ok_or(PlayerError::NoQueueFamily)?;
 
         let (mut swapchain, images) = {
-            let capabilities = surface.capabilities(physical).unwrap();
+            let capabilities = surface.capabilities(physical).map_err(PlayerError::Capabilities)?;
     
             println!("Supported formats:");
             for f in &capabilities.supported_formats {
@@ -194,7 +264,7 @@ impl Player {
     
             let format = Format::B8G8R8A8Srgb;
             if !capabilities.supported_formats.iter().any(|(f, _)| f == &format) {
-                panic!("Unsupported swapchain format {:?}", format);
+                return Err(PlayerError::UnsupportedFormat(format));
             }
     
             let dimensions: [u32; 2] = surface.window().inner_size().into();
@@ -214,7 +284,7 @@ impl Player {
                 FullscreenExclusive::Default,
                 true,
                 ColorSpace::SrgbNonLinear
-            ).unwrap()
+            ).map_err(PlayerError::Swapchain)?
         };
 
         let vertex_buffer = {
@@ -238,11 +308,11 @@ impl Player {
                 ]
                 .iter()
                 .cloned()
-            ).unwrap()
+            ).map_err(graphics_error("vertex buffer"))?
         };
 
-        let vs = vs::Shader::load(device.clone()).unwrap();
-        let fs = fs::Shader::load(device.clone()).unwrap();
+        let vs = vs::Shader::load(device.clone()).map_err(graphics_error("vertex shader"))?;
+        let fs = fs::Shader::load(device.clone()).map_err(graphics_error("fragment shader"))?;
 
         let render_pass = Arc::new(
             vulkano::single_pass_renderpass!(
@@ -259,11 +329,11 @@ impl Player {
                     color: [color],
                     depth_stencil: {}
                 }
-            ).unwrap()
+            ).map_err(graphics_error("render pass"))?
         );
 
         let mut tex_ratio = (1 as f32) / (1 as f32);
-        let (mut frame_image, mut texture_buffer) = alloc_video_frame_buffers(device.clone(), queue.family(), 1, 1);
+        let (mut frame_image, mut texture_buffer) = alloc_video_frame_buffers(device.clone(), queue.family(), 1, 1)?;
 
         let sampler = Sampler::new(
             device.clone(),
@@ -277,7 +347,7 @@ impl Player {
             1.0,
             0.0,
             0.0
-        ).unwrap();
+        ).map_err(graphics_error("sampler"))?;
 
         let pipeline = Arc::new(
             GraphicsPipeline::start()
@@ -286,9 +356,9 @@ impl Player {
                 .triangle_list()
                 .viewports_dynamic_scissors_irrelevant(1)
                 .fragment_shader(fs.main_entry_point(), ())
-                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .render_pass(Subpass::from(render_pass.clone(), 0).ok_or(PlayerError::Graphics("subpass", "Render pass has no subpass".into()))?)
                 .build(device.clone())
-                .unwrap()
+                .map_err(graphics_error("pipeline"))?
         );
 
         let mut dynamic_state = DynamicState {
@@ -300,16 +370,18 @@ impl Player {
             reference: None,
         };
 
-        let layout = pipeline.layout().descriptor_set_layout(0).unwrap().clone();
+        let layout = pipeline.layout().descriptor_set_layout(0)
+            .ok_or(PlayerError::Graphics("descriptor set", "Pipeline has no descriptor set layout".into()))?
+            .clone();
         let mut set = Arc::new(
             PersistentDescriptorSet::start(layout.clone())
                 .add_sampled_image(frame_image.clone(), sampler.clone())
-                .unwrap()
+                .map_err(graphics_error("descriptor set"))?
                 .build()
-                .unwrap(),
+                .map_err(graphics_error("descriptor set"))?,
         );
 
-        let mut framebuffers = window_size_dependent_setup(&images, render_pass.clone(), &mut dynamic_state);
+        let mut framebuffers = window_size_dependent_setup(&images, render_pass.clone(), &mut dynamic_state)?;
 
         let mut recreate_swapchain = false;
         let mut previous_frame_end = Some(sync::now(device.clone()).boxed());
@@ -317,6 +389,20 @@ impl Player {
         event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Poll;
 
+            // Rendering errors close the player instead of panicking inside the event loop
+            macro_rules! try_or_exit {
+                ($x:expr, $msg:expr) => {
+                    match $x {
+                        Ok(value) => value,
+                        Err(e) => {
+                            println!("{}: {:?}", $msg, e);
+                            *control_flow = ControlFlow::Exit;
+                            return;
+                        }
+                    }
+                };
+            }
+
             match event {
                 Event::WindowEvent {
                     event:  WindowEvent::CloseRequested,
@@ -324,6 +410,8 @@ impl Player {
                 } => {
                     *control_flow = ControlFlow::Exit;
                 },
+                // Sent once the loop exits for whatever reason, right before the process ends
+                Event::LoopDestroyed => run_exit_handler(&exit_handler),
                 Event::WindowEvent {
                     event:  WindowEvent::Resized(_),
                     ..
@@ -331,30 +419,36 @@ impl Player {
                     recreate_swapchain = true;
                 },
                 Event::RedrawEventsCleared => {
-                    previous_frame_end.as_mut().unwrap().cleanup_finished();
+                    if let Some(previous_frame_end) = previous_frame_end.as_mut() {
+                        previous_frame_end.cleanup_finished();
+                    }
+
+                    if let Some(status) = self.status_receiver.as_ref().and_then(|receiver| receiver.try_iter().last()) {
+                        surface.window().set_title(&format!("{} - {}", WINDOW_TITLE, status));
+                    }
 
-                    let next_frame = self.receiver.try_recv();
                     let mut update_image = false;
-                    if !next_frame.is_err() {
-                        let frame = next_frame.unwrap();
+                    if let Ok(frame) = self.receiver.try_recv() {
                         if frame.data.len() != texture_buffer.size() {
                             println!("Allocating new buffers for image ({}, {})", frame.width, frame.height);
                             tex_ratio = (frame.width as f32) / (frame.height as f32);
-                            let (new_frame_image, new_texture_buffer) = alloc_video_frame_buffers(
-                                device.clone(), queue.family(), frame.width, frame.height);
+                            let (new_frame_image, new_texture_buffer) = try_or_exit!(alloc_video_frame_buffers(
+                                device.clone(), queue.family(), frame.width, frame.height), "Failed to allocate frame buffers");
                             frame_image = new_frame_image;
                             texture_buffer = new_texture_buffer;
 
-                            set = Arc::new(PersistentDescriptorSet::start(layout.clone())
-                                    .add_sampled_image(frame_image.clone(), sampler.clone())
-                                    .unwrap()
-                                    .build()
-                                    .unwrap());
+                            let new_set = try_or_exit!(PersistentDescriptorSet::start(layout.clone())
+                                    .add_sampled_image(frame_image.clone(), sampler.clone()), "Failed to bind frame image");
+                            set = Arc::new(try_or_exit!(new_set.build(), "Failed to build descriptor set"));
                         }
 
-                        let mut writer = texture_buffer.write().unwrap();
-                        writer.copy_from_slice(&frame.data);
-                        update_image = true;
+                        let mut writer = try_or_exit!(texture_buffer.write(), "Failed to lock texture buffer");
+                        if writer.len() == frame.data.len() {
+                            writer.copy_from_slice(&frame.data);
+                            update_image = true;
+                        } else {
+                            println!("Dropping frame with {} bytes for a {}x{} image", frame.data.len(), frame.width, frame.height);
+                        }
                     }
     
                     if recreate_swapchain {
@@ -363,11 +457,16 @@ impl Player {
                             match swapchain.recreate_with_dimensions(dimensions) {
                                 Ok(r) => r,
                                 Err(SwapchainCreationError::UnsupportedDimensions) => return,
-                                Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+                                Err(e) => {
+                                    println!("Failed to recreate swapchain: {:?}", e);
+                                    *control_flow = ControlFlow::Exit;
+                                    return;
+                                }
                             };
 
                         swapchain = new_swapchain;
-                        framebuffers = window_size_dependent_setup(&new_images, render_pass.clone(), &mut dynamic_state);
+                        framebuffers = try_or_exit!(window_size_dependent_setup(&new_images, render_pass.clone(), &mut dynamic_state),
+                                                    "Failed to recreate framebuffers");
                         recreate_swapchain = false;
                     }
 
@@ -378,7 +477,11 @@ impl Player {
                                 recreate_swapchain = true;
                                 return;
                             }
-                            Err(e) => panic!("Failed to acquire next image: {:?}", e),
+                            Err(e) => {
+                                println!("Failed to acquire next image: {:?}", e);
+                                *control_flow = ControlFlow::Exit;
+                                return;
+                            }
                         };
 
                     if suboptimal {
@@ -393,42 +496,40 @@ impl Player {
                         tex_ratio
                     };
 
-                    let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+                    let mut builder = try_or_exit!(AutoCommandBufferBuilder::primary_one_time_submit(
                         device.clone(),
                         queue.family(),
-                    ).unwrap();
+                    ), "Failed to create command buffer");
 
                     if update_image {
-                        builder.copy_buffer_to_image(texture_buffer.clone(), frame_image.clone()).unwrap();
+                        try_or_exit!(builder.copy_buffer_to_image(texture_buffer.clone(), frame_image.clone()),
+                                     "Failed to copy frame to image");
                     }
 
-                    builder
-                        .begin_render_pass(framebuffers[image_num].clone(), false, clear_values)
-                        .unwrap()
-                        .draw(
-                            pipeline.clone(),
-                            &dynamic_state,
-                            vertex_buffer.clone(),
-                            set.clone(),
-                            push_constants,
-                        )
-                        .unwrap()
-                        .end_render_pass()
-                        .unwrap();
-
-                    let command_buffer = builder.build().unwrap();
-
-                    let future = previous_frame_end
-                        .take().unwrap()
+                    try_or_exit!(builder.begin_render_pass(framebuffers[image_num].clone(), false, clear_values),
+                                 "Failed to begin render pass");
+                    try_or_exit!(builder.draw(
+                        pipeline.clone(),
+                        &dynamic_state,
+                        vertex_buffer.clone(),
+                        set.clone(),
+                        push_constants,
+                    ), "Failed to draw frame");
+                    try_or_exit!(builder.end_render_pass(), "Failed to end render pass");
+
+                    let command_buffer = try_or_exit!(builder.build(), "Failed to build command buffer");
+
+                    let previous = previous_frame_end.take().unwrap_or_else(|| sync::now(device.clone()).boxed());
+                    let execution = try_or_exit!(previous
                         .join(acquire_future)
-                        .then_execute(queue.clone(), command_buffer)
-                        .unwrap()
+                        .then_execute(queue.clone(), command_buffer), "Failed to execute command buffer");
+                    let future = execution
                         .then_swapchain_present(queue.clone(), swapchain.clone(), image_num)
                         .then_signal_fence_and_flush();
 
                     match future {
                         Ok(future) => {
-                            future.wait(None).unwrap();
+                            try_or_exit!(future.wait(None), "Failed to wait for frame");
                             previous_frame_end = Some(future.boxed());
                         }
                         Err(FlushError::OutOfDate) => {
@@ -447,48 +548,3 @@ impl Player {
         });
     }
 }
-
-fn parse_png_from_bytes(png_bytes: Vec<u8>) -> Frame {
-    let cursor = Cursor::new(png_bytes);
-    let decoder = png::Decoder::new(cursor);
-    let (info, mut reader) = decoder.read_info().unwrap();
-    let mut image_data = Vec::new();
-    image_data.resize((info.width * info.height * 4) as usize, 0);
-    reader.next_frame(&mut image_data).unwrap();
-
-    Frame {
-        width: info.width,
-        height: info.height,
-        data: image_data
-    }
-}
-
-fn main() {
-    let (sender, receiver) = channel();
-    let mut player = Player::new(receiver);
-
-    let is_sending = Arc::new(AtomicBool::new(true));
-    let is_sending_clone = is_sending.clone();
-    let sender_thread = thread::spawn(move || {
-        let frames = vec![
-            parse_png_from_bytes(include_bytes!("test_image.png").to_vec()),
-            parse_png_from_bytes(include_bytes!("test_image_2.png").to_vec())
-        ];
-        let mut frames_iter = frames.iter().cycle();
-
-        while (*is_sending_clone).load(Ordering::Relaxed) {
-            let frame = frames_iter.next().unwrap();
-            sender.send(Frame {
-                width: frame.width,
-                height: frame.height,
-                data: frame.data.clone()
-            }).expect("Failed to send frame");
-            thread::sleep(time::Duration::from_millis(1000));
-        }
-    });
-
-    player.run();
-
-    is_sending.store(false, Ordering::Relaxed);
-    sender_thread.join().unwrap();
-}
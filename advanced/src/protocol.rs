@@ -0,0 +1,647 @@
+use std::fmt;
+use std::error::Error;
+
+use crate::crc;
+use crate::file_transfer;
+
+use chrono::{ NaiveTime, Utc, Timelike };
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelloGram {
+    discriminator: u8,
+    id: u16,
+    sequence: u16,
+    payload: Vec<u8>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    TooShort(usize),
+    InvalidHeader(u8),
+    SizeMismatch { declared: usize, actual: usize },
+    InvalidCrc8 { expected: u8, actual: u8 },
+    InvalidCrc16 { expected: u16, actual: u16 },
+    // A well formed TelloGram whose payload is too short for its id
+    TruncatedPayload { id: u16, length: usize }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::TooShort(length) => write!(f, "TelloGram of {} bytes is too short", length),
+            ParseError::InvalidHeader(header) => write!(f, "Invalid TelloGram header {:#x}", header),
+            ParseError::SizeMismatch { declared, actual } => write!(f, "TelloGram declares {} bytes but has {}", declared, actual),
+            ParseError::InvalidCrc8 { expected, actual } => write!(f, "Invalid header CRC {:#x}, expected {:#x}", actual, expected),
+            ParseError::InvalidCrc16 { expected, actual } => write!(f, "Invalid CRC {:#x}, expected {:#x}", actual, expected),
+            ParseError::TruncatedPayload { id, length } => write!(f, "Payload of {} bytes is too short for {:#x}", length, id),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TelloGramDirection {
+    ToDrone, FromDrone, Unknown
+}
+
+impl TelloGramDirection {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            TelloGramDirection::FromDrone => 0x80,
+            TelloGramDirection::ToDrone => 0x40,
+            TelloGramDirection::Unknown => 0
+        }
+    }
+
+    pub fn from_u8(discriminator: u8) -> TelloGramDirection {
+        match discriminator {
+            val if (val & 0x80) != 0 => TelloGramDirection::FromDrone,
+            val if (val & 0x40) != 0 => TelloGramDirection::ToDrone,
+            _ => TelloGramDirection::Unknown
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlipDirection {
+    Forward,
+    Left,
+    Backward,
+    Right,
+    ForwardLeft,
+    BackwardLeft,
+    BackwardRight,
+    ForwardRight
+}
+
+// Fast mode roughly doubles the speed reached at full stick deflection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpeedMode {
+    Slow,
+    Fast
+}
+
+// Encoder bitrate of the video stream, Auto lets the drone adapt it to the link quality
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoBitrate {
+    Auto = 0,
+    Mbps1 = 1,
+    Mbps1_5 = 2,
+    Mbps2 = 3,
+    Mbps3 = 4,
+    Mbps4 = 5
+}
+
+// Normal streams 960x720 at 4:3, Wide streams 1280x720 at 16:9
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoMode {
+    Normal = 0,
+    Wide = 1
+}
+
+// The three exposure levels offered by the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exposure {
+    Low = 0,
+    Medium = 1,
+    High = 2
+}
+
+#[derive(Debug)]
+pub enum Commands {
+    VideoSPSPPS,
+    SetVideoBitrate(VideoBitrate),
+    SetVideoMode(VideoMode),
+    SetExposure(Exposure),
+    Takeoff,
+    Land,
+    ThrowTakeoff,
+    PalmLand,
+    SetMaxHeight(u16),
+    GetMaxHeight,
+    SetLowBatteryThreshold(u8),
+    GetLowBatteryThreshold,
+    SetAttitudeAngle(f32),
+    GetAttitudeAngle,
+    GetVersion,
+    GetLoaderVersion,
+    TakePicture,
+    FileSizeAck,
+    FilePieceAck { file_id: u16, piece: u32, is_last: bool },
+    FileDone { file_id: u16, size: u32 },
+    Joystick { lx: f32, ly: f32, rx: f32, ry: f32, speed_mode: SpeedMode },
+    Flip(FlipDirection),
+    LogHeaderAck(u16)
+}
+
+impl Commands {
+    pub fn id(&self) -> u16 {
+        match self {
+            Commands::VideoSPSPPS => 0x25,
+            Commands::SetVideoBitrate(_) => 0x20,
+            Commands::SetVideoMode(_) => 0x31,
+            Commands::SetExposure(_) => 0x34,
+            Commands::Takeoff => 0x54,
+            Commands::Land => 0x55,
+            Commands::ThrowTakeoff => 0x5d,
+            Commands::PalmLand => 0x5e,
+            Commands::SetMaxHeight(_) => 0x58,
+            Commands::GetMaxHeight => 0x1056,
+            Commands::SetLowBatteryThreshold(_) => 0x1055,
+            Commands::GetLowBatteryThreshold => 0x1057,
+            Commands::SetAttitudeAngle(_) => 0x1058,
+            Commands::GetAttitudeAngle => 0x1059,
+            Commands::GetVersion => 0x45,
+            Commands::GetLoaderVersion => 0x49,
+            Commands::TakePicture => 0x30,
+            Commands::FileSizeAck => 0x62,
+            Commands::FilePieceAck { .. } => 0x63,
+            Commands::FileDone { .. } => 0x64,
+            Commands::Joystick { .. } => 0x50,
+            Commands::Flip(_) => 0x5c,
+            Commands::LogHeaderAck(_) => 0x1050,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PackageType {
+    Extended,
+    Get,
+    Data1,
+    Data2,
+    Set,
+    Flip
+}
+
+impl PackageType {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            PackageType::Extended => 0,
+            PackageType::Get => 1,
+            PackageType::Data1 => 2,
+            PackageType::Data2 => 4,
+            PackageType::Set => 5,
+            PackageType::Flip => 6
+        }
+    }
+
+    pub fn from_u8(packet_type: u8) -> Option<PackageType> {
+        match packet_type {
+            0 => Some(PackageType::Extended),
+            1 => Some(PackageType::Get),
+            2 => Some(PackageType::Data1),
+            4 => Some(PackageType::Data2),
+            5 => Some(PackageType::Set),
+            6 => Some(PackageType::Flip),
+            _ => None
+        }
+    }
+}
+
+impl TelloGram {
+    pub const HEADER: u8 = 0xcc;
+    pub const GRAM_SIZE: usize = 11;
+    const PAYLOAD_OFFSET: usize = 9;
+
+    pub fn new(direction: TelloGramDirection,
+           packet_type: PackageType,
+           packet_subtype: u8,
+           id: u16,
+           sequence: u16,
+           payload: &[u8]) -> TelloGram {
+        TelloGram {
+            discriminator: direction.to_u8() | ((packet_type.to_u8() & 0x7) << 3) | (packet_subtype & 0x7),
+            id,
+            sequence,
+            payload: payload.to_vec()
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<TelloGram, ParseError> {
+        if bytes.len() < TelloGram::GRAM_SIZE {
+            return Err(ParseError::TooShort(bytes.len()));
+        }
+        if bytes[0] != TelloGram::HEADER {
+            return Err(ParseError::InvalidHeader(bytes[0]));
+        }
+
+        let declared = (u16::from_le_bytes([bytes[1], bytes[2]]) >> 3) as usize;
+        if declared != bytes.len() {
+            return Err(ParseError::SizeMismatch { declared, actual: bytes.len() });
+        }
+
+        let crc8 = crc::calculate_crc8(&bytes[..3]);
+        if crc8 != bytes[3] {
+            return Err(ParseError::InvalidCrc8 { expected: bytes[3], actual: crc8 });
+        }
+
+        let crc16 = crc::calculate_crc16(&bytes[..declared - 2]);
+        let expected_crc16 = u16::from_le_bytes([bytes[declared - 2], bytes[declared - 1]]);
+        if crc16 != expected_crc16 {
+            return Err(ParseError::InvalidCrc16 { expected: expected_crc16, actual: crc16 });
+        }
+
+        Ok(TelloGram {
+            discriminator: bytes[4],
+            id: u16::from_le_bytes([bytes[5], bytes[6]]),
+            sequence: u16::from_le_bytes([bytes[7], bytes[8]]),
+            payload: bytes[TelloGram::PAYLOAD_OFFSET..declared - 2].to_vec()
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let packet_size = self.size();
+
+        let mut buffer = Vec::with_capacity(packet_size);
+        buffer.push(TelloGram::HEADER);
+        buffer.extend_from_slice(&((packet_size << 3) as u16).to_le_bytes());
+        buffer.push(crc::calculate_crc8(&buffer[..3]));
+        buffer.push(self.discriminator);
+        buffer.extend_from_slice(&self.id.to_le_bytes());
+        buffer.extend_from_slice(&self.sequence.to_le_bytes());
+        buffer.extend_from_slice(&self.payload);
+
+        let crc16 = crc::calculate_crc16(&buffer);
+        buffer.extend_from_slice(&crc16.to_le_bytes());
+
+        buffer
+    }
+
+    pub fn size(&self) -> usize {
+        TelloGram::GRAM_SIZE + self.payload.len()
+    }
+
+    pub fn packet_direction(&self) -> TelloGramDirection {
+        TelloGramDirection::from_u8(self.discriminator)
+    }
+
+    pub fn packet_type(&self) -> Option<PackageType> {
+        PackageType::from_u8((self.discriminator >> 3) & 0x7)
+    }
+
+    pub fn packet_subtype(&self) -> u8 {
+        self.discriminator & 0x7
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    // All commands seen from the official app use subtype 0, the subtype bits are only kept
+    // so packets from the drone survive a parse/encode round trip.
+    pub fn construct_package(packet_type: PackageType, command: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
+        TelloGram::new(TelloGramDirection::ToDrone, packet_type, 0, command, seq, payload).to_bytes()
+    }
+
+    fn tello_position(position: f32) -> u64 {
+        (1024f32 + (position * 660f32)) as u64
+    }
+
+    pub fn joystick_payload(lx: f32, ly: f32, rx: f32, ry: f32, speed_mode: SpeedMode, time: NaiveTime) -> [u8; 11] {
+        let mut encoded_position = Self::tello_position(lx) & 0x7ff;
+        encoded_position |= (Self::tello_position(-ly) & 0x7ff) << 11;
+        encoded_position |= (Self::tello_position(-ry) & 0x7ff) << 22;
+        encoded_position |= (Self::tello_position(rx) & 0x7ff) << 33;
+        if speed_mode == SpeedMode::Fast {
+            encoded_position |= 1u64 << 44;
+        }
+
+        let mut payload = [0u8; 11];
+        for i in 0..6 {
+            payload[i] = (encoded_position >> (8 * i)) as u8;
+        }
+
+        payload[6] = time.hour() as u8;
+        payload[7] = time.minute() as u8;
+        payload[8] = time.second() as u8;
+        let ms = time.nanosecond() / 1_000_000;
+        payload[9] = ms as u8;
+        payload[10] = (ms >> 8) as u8;
+
+        payload
+    }
+
+    // Decodes the four stick axes of a joystick payload in packet order, scaled to [-1, 1]
+    pub fn joystick_axes(payload: &[u8]) -> Option<[f32; 4]> {
+        if payload.len() < 6 {
+            return None;
+        }
+
+        let mut encoded_position = 0u64;
+        for i in 0..6 {
+            encoded_position |= (payload[i] as u64) << (8 * i);
+        }
+
+        let mut axes = [0f32; 4];
+        for (i, axis) in axes.iter_mut().enumerate() {
+            let raw = (encoded_position >> (11 * i)) & 0x7ff;
+            *axis = (raw as f32 - 1024f32) / 660f32;
+        }
+        Some(axes)
+    }
+
+    pub fn joystick_speed_mode(payload: &[u8]) -> Option<SpeedMode> {
+        let flags = *payload.get(5)?;
+        // Bit 44 of the packed stick positions
+        Some(if flags & 0x10 != 0 { SpeedMode::Fast } else { SpeedMode::Slow })
+    }
+
+    pub fn from(command: Commands, seq: u16) -> Vec<u8> {
+        let id = command.id();
+        match command {
+            Commands::VideoSPSPPS => TelloGram::construct_package(PackageType::Data2, id, seq, &[]),
+            Commands::SetVideoBitrate(bitrate) => TelloGram::construct_package(PackageType::Set, id, seq, &[bitrate as u8]),
+            Commands::SetVideoMode(mode) => TelloGram::construct_package(PackageType::Set, id, seq, &[mode as u8]),
+            Commands::SetExposure(exposure) => TelloGram::construct_package(PackageType::Set, id, seq, &[exposure as u8]),
+            Commands::Takeoff => TelloGram::construct_package(PackageType::Set, id, seq, &[]),
+            Commands::Land => TelloGram::construct_package(PackageType::Set, id, seq, &vec![0]),
+            Commands::ThrowTakeoff => TelloGram::construct_package(PackageType::Get, id, seq, &[0]),
+            Commands::PalmLand => TelloGram::construct_package(PackageType::Set, id, seq, &[0]),
+            Commands::SetMaxHeight(meters) => TelloGram::construct_package(PackageType::Set, id, seq, &meters.to_le_bytes()),
+            Commands::SetLowBatteryThreshold(percentage) => TelloGram::construct_package(PackageType::Set, id, seq, &[percentage]),
+            Commands::SetAttitudeAngle(degrees) => TelloGram::construct_package(PackageType::Set, id, seq, &degrees.to_le_bytes()),
+            Commands::GetMaxHeight |
+            Commands::GetLowBatteryThreshold |
+            Commands::GetAttitudeAngle |
+            Commands::GetVersion |
+            Commands::GetLoaderVersion => {
+                TelloGram::construct_package(PackageType::Get, id, seq, &[])
+            },
+            Commands::TakePicture => TelloGram::construct_package(PackageType::Set, id, seq, &[]),
+            Commands::FileSizeAck => TelloGram::construct_package(PackageType::Data1, id, seq, &[0]),
+            Commands::FilePieceAck { file_id, piece, is_last } => {
+                TelloGram::construct_package(PackageType::Data1, id, seq, &file_transfer::piece_ack(file_id, piece, is_last))
+            },
+            Commands::FileDone { file_id, size } => {
+                TelloGram::construct_package(PackageType::Get, id, seq, &file_transfer::file_done(file_id, size))
+            },
+            Commands::Joystick { lx, ly, rx, ry, speed_mode } => {
+                let payload = Self::joystick_payload(lx, ly, rx, ry, speed_mode, Utc::now().time());
+                TelloGram::construct_package(PackageType::Data2, id, 0, &payload)
+            },
+            Commands::Flip(direction) => {
+                TelloGram::construct_package(PackageType::Flip, id, seq, &[direction as u8])
+            },
+            Commands::LogHeaderAck(log_id) => {
+                let log_id = log_id.to_le_bytes();
+                TelloGram::construct_package(PackageType::Data1, id, seq, &[0, log_id[0], log_id[1]])
+            },
+        }
+    }
+}
+
+pub trait NetworkPackage {
+    fn as_bytes(&self) -> Vec<u8>;
+}
+
+pub struct TelloConnectRequest<'a> {
+    cmd: &'a str,
+    video_port: u16
+}
+
+impl<'a> TelloConnectRequest<'a> {
+    pub fn connect(video_port: u16) -> TelloConnectRequest<'a> {
+        TelloConnectRequest {
+            cmd: "conn_req",
+            video_port
+        }
+    }
+}
+
+impl<'a> NetworkPackage for TelloConnectRequest<'a> {
+    fn as_bytes(&self) -> Vec<u8> {
+        const COLON: u8 = ':' as u8;
+        let command_bytes = self.cmd.as_bytes();
+        let mut bytes: Vec<u8> = Vec::with_capacity(command_bytes.len() + 3);
+
+        for byte in command_bytes { bytes.push(*byte) }
+        bytes.push(COLON);
+        bytes.extend_from_slice(&self.video_port.to_le_bytes());
+        
+        bytes
+    }
+}
+
+
+#[cfg(test)]
+const FLIGHT_DATA_PACKET: [u8; 35] = [
+    204, 24, 1, 185, 136, 86, 0, 32, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 81, 0, 0, 252, 15, 0, 6, 0, 0, 0, 0, 0, 66, 206
+];
+
+#[test]
+fn test_parse_flight_data_packet() {
+    let gram = TelloGram::parse(&FLIGHT_DATA_PACKET).expect("Failed to parse captured packet");
+    assert_eq!(gram.id(), 0x56);
+    assert_eq!(gram.sequence(), 0x320);
+    assert_eq!(gram.packet_direction(), TelloGramDirection::FromDrone);
+    assert_eq!(gram.packet_type(), Some(PackageType::Get));
+    assert_eq!(gram.packet_subtype(), 0);
+    assert_eq!(gram.size(), FLIGHT_DATA_PACKET.len());
+    assert_eq!(gram.payload(), &FLIGHT_DATA_PACKET[9..33]);
+    assert_eq!(gram.to_bytes(), FLIGHT_DATA_PACKET.to_vec());
+}
+
+#[test]
+fn test_gram_round_trip() {
+    let gram = TelloGram::new(TelloGramDirection::ToDrone, PackageType::Flip, 0, 0x5c, 42, &[1, 2, 3]);
+    let bytes = gram.to_bytes();
+    assert_eq!(bytes.len(), TelloGram::GRAM_SIZE + 3);
+    assert_eq!(crc::calculate_crc8(&bytes[..3]), bytes[3]);
+    assert_eq!(TelloGram::parse(&bytes), Ok(gram));
+
+    let empty = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Set, 3, 0x54, 7, &[]);
+    assert_eq!(TelloGram::parse(&empty.to_bytes()), Ok(empty));
+}
+
+#[test]
+fn test_parse_truncated_gram() {
+    for length in 0..TelloGram::GRAM_SIZE {
+        assert_eq!(TelloGram::parse(&FLIGHT_DATA_PACKET[..length]), Err(ParseError::TooShort(length)));
+    }
+    for length in TelloGram::GRAM_SIZE..FLIGHT_DATA_PACKET.len() {
+        assert_eq!(
+            TelloGram::parse(&FLIGHT_DATA_PACKET[..length]),
+            Err(ParseError::SizeMismatch { declared: FLIGHT_DATA_PACKET.len(), actual: length })
+        );
+    }
+
+    let mut padded = FLIGHT_DATA_PACKET.to_vec();
+    padded.push(0);
+    assert_eq!(
+        TelloGram::parse(&padded),
+        Err(ParseError::SizeMismatch { declared: FLIGHT_DATA_PACKET.len(), actual: padded.len() })
+    );
+}
+
+#[test]
+fn test_parse_corrupted_gram() {
+    let mut bad_header = FLIGHT_DATA_PACKET;
+    bad_header[0] = 0xcd;
+    assert_eq!(TelloGram::parse(&bad_header), Err(ParseError::InvalidHeader(0xcd)));
+
+    let mut bad_crc8 = FLIGHT_DATA_PACKET;
+    bad_crc8[3] = 0;
+    assert_eq!(TelloGram::parse(&bad_crc8), Err(ParseError::InvalidCrc8 { expected: 0, actual: 185 }));
+
+    let mut bad_payload = FLIGHT_DATA_PACKET;
+    bad_payload[20] ^= 0xff;
+    assert_eq!(
+        TelloGram::parse(&bad_payload),
+        Err(ParseError::InvalidCrc16 { expected: 52802, actual: crc::calculate_crc16(&bad_payload[..33]) })
+    );
+}
+
+#[test]
+fn test_package_type_encoding() {
+    for packet_type in 0..8 {
+        if let Some(decoded) = PackageType::from_u8(packet_type) {
+            assert_eq!(decoded.to_u8(), packet_type);
+        }
+    }
+
+    for &direction in &[TelloGramDirection::ToDrone, TelloGramDirection::FromDrone, TelloGramDirection::Unknown] {
+        for packet_type in &[PackageType::Extended, PackageType::Get, PackageType::Data1,
+                             PackageType::Data2, PackageType::Set, PackageType::Flip] {
+            for subtype in 0..8 {
+                let gram = TelloGram::parse(&TelloGram::new(direction, *packet_type, subtype, 0x54, 0, &[]).to_bytes()).unwrap();
+                assert_eq!(gram.packet_direction(), direction);
+                assert_eq!(gram.packet_type(), Some(*packet_type));
+                assert_eq!(gram.packet_subtype(), subtype);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_takeoff_package() {
+    let expected = [0xcc, 0x58, 0x00, 0x7c, 0x68, 0x54, 0x00, 0xe4, 0x01, 0xc2, 0x16];
+    assert_eq!(TelloGram::from(Commands::Takeoff, 0x1e4), expected.to_vec());
+
+    let gram = TelloGram::parse(&expected).unwrap();
+    assert_eq!(gram.packet_direction(), TelloGramDirection::ToDrone);
+    assert_eq!(gram.packet_type(), Some(PackageType::Set));
+    assert_eq!(gram.packet_subtype(), 0);
+}
+
+#[test]
+fn test_land_package() {
+    let expected = [0xcc, 0x60, 0x00, 0x27, 0x68, 0x55, 0x00, 0xe5, 0x01, 0x00, 0xba, 0xc7];
+    assert_eq!(TelloGram::from(Commands::Land, 0x1e5), expected.to_vec());
+}
+
+#[test]
+fn test_throw_takeoff_and_palm_land_packages() {
+    let expected = [0xcc, 0x60, 0x00, 0x27, 0x48, 0x5d, 0x00, 0xe7, 0x01, 0x00, 0x42, 0xad];
+    assert_eq!(TelloGram::from(Commands::ThrowTakeoff, 0x1e7), expected.to_vec());
+
+    let expected = [0xcc, 0x60, 0x00, 0x27, 0x68, 0x5e, 0x00, 0xe8, 0x01, 0x00, 0x29, 0x7f];
+    assert_eq!(TelloGram::from(Commands::PalmLand, 0x1e8), expected.to_vec());
+}
+
+#[test]
+fn test_setting_packages() {
+    let set_max_height = TelloGram::parse(&TelloGram::from(Commands::SetMaxHeight(0x0123), 1)).unwrap();
+    assert_eq!(set_max_height.packet_type(), Some(PackageType::Set));
+    assert_eq!(set_max_height.id(), 0x58);
+    assert_eq!(set_max_height.payload(), &[0x23, 0x01]);
+
+    let set_threshold = TelloGram::parse(&TelloGram::from(Commands::SetLowBatteryThreshold(25), 2)).unwrap();
+    assert_eq!(set_threshold.id(), 0x1055);
+    assert_eq!(set_threshold.payload(), &[25]);
+
+    let set_attitude = TelloGram::parse(&TelloGram::from(Commands::SetAttitudeAngle(25.0), 3)).unwrap();
+    assert_eq!(set_attitude.id(), 0x1058);
+    assert_eq!(set_attitude.payload(), &25f32.to_le_bytes());
+
+    let query = |command| {
+        let gram = TelloGram::parse(&TelloGram::from(command, 4)).unwrap();
+        assert_eq!(gram.packet_type(), Some(PackageType::Get));
+        assert_eq!(gram.sequence(), 4);
+        assert_eq!(gram.payload(), &[]);
+        gram.id()
+    };
+    assert_eq!(query(Commands::GetMaxHeight), 0x1056);
+    assert_eq!(query(Commands::GetLowBatteryThreshold), 0x1057);
+    assert_eq!(query(Commands::GetAttitudeAngle), 0x1059);
+    assert_eq!(query(Commands::GetVersion), 0x45);
+    assert_eq!(query(Commands::GetLoaderVersion), 0x49);
+}
+
+#[test]
+fn test_video_setting_packages() {
+    let setting = |command| {
+        let gram = TelloGram::parse(&TelloGram::from(command, 9)).unwrap();
+        assert_eq!(gram.packet_type(), Some(PackageType::Set));
+        assert_eq!(gram.sequence(), 9);
+        (gram.id(), gram.payload().to_vec())
+    };
+    assert_eq!(setting(Commands::SetVideoBitrate(VideoBitrate::Auto)), (0x20, vec![0]));
+    assert_eq!(setting(Commands::SetVideoBitrate(VideoBitrate::Mbps1_5)), (0x20, vec![2]));
+    assert_eq!(setting(Commands::SetVideoMode(VideoMode::Wide)), (0x31, vec![1]));
+    assert_eq!(setting(Commands::SetExposure(Exposure::High)), (0x34, vec![2]));
+}
+
+#[test]
+fn test_file_transfer_packages() {
+    let take_picture = TelloGram::parse(&TelloGram::from(Commands::TakePicture, 5)).unwrap();
+    assert_eq!(take_picture.packet_type(), Some(PackageType::Set));
+    assert_eq!(take_picture.id(), 0x30);
+    assert_eq!(take_picture.payload(), &[]);
+
+    let size_ack = TelloGram::parse(&TelloGram::from(Commands::FileSizeAck, 6)).unwrap();
+    assert_eq!(size_ack.id(), 0x62);
+    assert_eq!(size_ack.payload(), &[0]);
+
+    let piece_ack = TelloGram::parse(&TelloGram::from(Commands::FilePieceAck { file_id: 3, piece: 0x0102, is_last: false }, 7)).unwrap();
+    assert_eq!(piece_ack.packet_type(), Some(PackageType::Data1));
+    assert_eq!(piece_ack.id(), 0x63);
+    assert_eq!(piece_ack.payload(), &[0, 3, 0, 0x02, 0x01, 0, 0]);
+
+    // tellopy sends the file complete message with packet type 0x48
+    let done_bytes = TelloGram::from(Commands::FileDone { file_id: 3, size: 0x1234 }, 8);
+    assert_eq!(done_bytes[4], 0x48);
+    let done = TelloGram::parse(&done_bytes).unwrap();
+    assert_eq!(done.packet_type(), Some(PackageType::Get));
+    assert_eq!(done.id(), 0x64);
+    assert_eq!(done.payload(), &[3, 0, 0x34, 0x12, 0, 0]);
+}
+
+#[test]
+fn test_flip_package() {
+    let expected = [0xcc, 0x60, 0x00, 0x27, 0x70, 0x5c, 0x00, 0xe6, 0x01, 0x01, 0xdb, 0x0b];
+    assert_eq!(TelloGram::from(Commands::Flip(FlipDirection::Left), 0x1e6), expected.to_vec());
+    assert_eq!(TelloGram::parse(&expected).unwrap().packet_type(), Some(PackageType::Flip));
+}
+
+#[test]
+fn test_joystick_package() {
+    let expected = [
+        0xcc, 0xb0, 0x00, 0x7f, 0x60, 0x50, 0x00, 0x00, 0x00, 0x4a, 0xa5,
+        0xf4, 0xd6, 0x00, 0x08, 0x0c, 0x22, 0x38, 0x15, 0x03, 0xf2, 0x8b
+    ];
+    let time = NaiveTime::from_hms_milli_opt(12, 34, 56, 789).unwrap();
+    let payload = TelloGram::joystick_payload(0.5, -1.0, 0.0, 0.25, SpeedMode::Slow, time);
+    assert_eq!(TelloGram::construct_package(PackageType::Data2, 0x50, 0, &payload), expected.to_vec());
+
+    let gram = TelloGram::parse(&expected).unwrap();
+    assert_eq!(gram.packet_type(), Some(PackageType::Data2));
+    assert_eq!(gram.sequence(), 0);
+    assert_eq!(TelloGram::joystick_axes(gram.payload()), Some([0.5, 1.0, -0.25, 0.0]));
+    assert_eq!(TelloGram::joystick_speed_mode(gram.payload()), Some(SpeedMode::Slow));
+
+    let fast = TelloGram::joystick_payload(0.5, -1.0, 0.0, 0.25, SpeedMode::Fast, time);
+    assert_eq!(fast[5], payload[5] | 0x10);
+    assert_eq!(fast[..5], payload[..5]);
+    assert_eq!(TelloGram::joystick_axes(&fast), Some([0.5, 1.0, -0.25, 0.0]));
+    assert_eq!(TelloGram::joystick_speed_mode(&fast), Some(SpeedMode::Fast));
+}
@@ -0,0 +1,654 @@
+use std::fmt;
+use std::io;
+use std::error::Error;
+use std::net::{ SocketAddr, UdpSocket };
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use crate::controller;
+use crate::drone::{ DroneControl, DroneError };
+use crate::player;
+use crate::protocol::FlipDirection;
+use crate::sdk_state::{ SdkState, SdkStateListener, SDK_STATE_PORT };
+use crate::tello::Telemetry;
+use crate::video::{ VideoError, VideoStream };
+
+pub const SDK_CMD_PORT: u16 = 8889;
+pub const SDK_VIDEO_PORT: u16 = 11111;
+
+const VIDEO_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum SdkError {
+    Network(io::Error),
+    Timeout(String),
+    Interrupted(String),
+    Rejected { command: String, response: String },
+    InvalidResponse { command: String, response: String },
+    OutOfRange { argument: &'static str, value: i32, min: i32, max: i32 },
+    UnsupportedFlip(FlipDirection),
+    Video(VideoError),
+}
+
+impl fmt::Display for SdkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SdkError::Network(e) => write!(f, "Network error talking to Tello: {}", e),
+            SdkError::Timeout(command) => write!(f, "Timed out waiting for a response to '{}'", command),
+            SdkError::Interrupted(command) => write!(f, "'{}' was interrupted by an emergency stop", command),
+            SdkError::Rejected { command, response } => write!(f, "Tello rejected '{}': {}", command, response),
+            SdkError::InvalidResponse { command, response } => write!(f, "Unexpected response to '{}': {}", command, response),
+            SdkError::OutOfRange { argument, value, min, max } => {
+                write!(f, "{} must be between {} and {}, got {}", argument, min, max, value)
+            },
+            SdkError::UnsupportedFlip(direction) => write!(f, "The SDK cannot flip {:?}", direction),
+            SdkError::Video(e) => write!(f, "Failed to start video: {}", e),
+        }
+    }
+}
+
+impl Error for SdkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SdkError::Network(e) => Some(e),
+            SdkError::Video(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SdkError {
+    fn from(e: io::Error) -> SdkError {
+        SdkError::Network(e)
+    }
+}
+
+impl From<VideoError> for SdkError {
+    fn from(e: VideoError) -> SdkError {
+        SdkError::Video(e)
+    }
+}
+
+fn check_range(argument: &'static str, value: i32, min: i32, max: i32) -> Result<(), SdkError> {
+    if value < min || value > max {
+        return Err(SdkError::OutOfRange { argument, value, min, max });
+    }
+    Ok(())
+}
+
+// Query responses carry units like "dm", "mm", "s" or "C" which are stripped before parsing
+fn parse_number<T: FromStr>(command: &str, response: &str) -> Result<T, SdkError> {
+    response.trim_end_matches(|c: char| c.is_ascii_alphabetic())
+        .parse()
+        .map_err(|_| SdkError::InvalidResponse { command: String::from(command), response: String::from(response) })
+}
+
+// Parses "key:value;key:value;" responses, as returned by attitude? and acceleration?
+fn parse_fields<'a>(response: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+    response.split(';')
+        .filter_map(|field| {
+            let mut parts = field.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => Some((key.trim(), value.trim())),
+                _ => None
+            }
+        })
+}
+
+fn parse_field<T: FromStr>(command: &str, response: &str, key: &str) -> Result<T, SdkError> {
+    parse_fields(response)
+        .find(|&(field, _)| field == key)
+        .and_then(|(_, value)| value.parse().ok())
+        .ok_or_else(|| SdkError::InvalidResponse { command: String::from(command), response: String::from(response) })
+}
+
+// Client for the text based Tello SDK. Every command except rc is answered by the drone,
+// movement commands only once the movement has completed, so the response timeout has
+// to cover the longest movement being issued.
+pub struct SdkClient {
+    socket: UdpSocket,
+    response_timeout: Duration,
+    // Serializes request/response pairs so responses cannot be picked up by the wrong caller
+    command_lock: Mutex<()>,
+    // Counts emergency stops, whose answer a command waiting meanwhile must not take for its own
+    emergency_count: AtomicUsize,
+
+    state_listener: Option<SdkStateListener>,
+    video_stream: Option<VideoStream>,
+}
+
+impl SdkClient {
+    pub fn connect(drone_addr: SocketAddr, response_timeout: Duration) -> Result<SdkClient, SdkError> {
+        let bind_addr: SocketAddr = if drone_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(drone_addr)?;
+        socket.set_read_timeout(Some(response_timeout))?;
+
+        let client = SdkClient {
+            socket,
+            response_timeout,
+            command_lock: Mutex::new(()),
+            emergency_count: AtomicUsize::new(0),
+
+            state_listener: None,
+            video_stream: None,
+        };
+        client.command("command")?;
+        Ok(client)
+    }
+
+    pub fn response_timeout(&self) -> Duration {
+        self.response_timeout
+    }
+
+    // Sends a command and returns the raw response, error responses are turned into SdkError::Rejected
+    pub fn send_command(&self, command: &str) -> Result<String, SdkError> {
+        let _guard = self.command_lock.lock().unwrap();
+        self.drain_stale_responses()?;
+        let emergency_count = self.emergency_count.load(Ordering::SeqCst);
+        self.socket.send(command.as_bytes())?;
+
+        let mut buffer = [0u8; 1024];
+        let num_bytes = match self.socket.recv(&mut buffer) {
+            Ok(num_bytes) => num_bytes,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                return Err(SdkError::Timeout(String::from(command)));
+            },
+            Err(e) => return Err(SdkError::Network(e))
+        };
+        if self.emergency_count.load(Ordering::SeqCst) != emergency_count {
+            return Err(SdkError::Interrupted(String::from(command)));
+        }
+
+        let response = String::from_utf8_lossy(&buffer[..num_bytes]).trim().to_string();
+        if response.starts_with("error") {
+            return Err(SdkError::Rejected { command: String::from(command), response });
+        }
+        Ok(response)
+    }
+
+    // A response arriving after its command timed out must not be taken as the answer to the next one
+    fn drain_stale_responses(&self) -> Result<(), SdkError> {
+        let mut buffer = [0u8; 1024];
+        self.socket.set_nonblocking(true)?;
+        let result = loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                // Refused sends from earlier commands are reported here on some platforms
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(e) => break Err(SdkError::Network(e))
+            }
+        };
+        self.socket.set_nonblocking(false)?;
+        result
+    }
+
+    fn command(&self, command: &str) -> Result<(), SdkError> {
+        let response = self.send_command(command)?;
+        if response != "ok" {
+            return Err(SdkError::InvalidResponse { command: String::from(command), response });
+        }
+        Ok(())
+    }
+
+    fn query<T: FromStr>(&self, command: &str) -> Result<T, SdkError> {
+        let response = self.send_command(command)?;
+        parse_number(command, &response)
+    }
+
+    fn move_by(&self, direction: &str, cm: i32) -> Result<(), SdkError> {
+        check_range("distance", cm, 20, 500)?;
+        self.command(&format!("{} {}", direction, cm))
+    }
+
+    // The drone broadcasts its state to port 8890 of every client that sent "command"
+    pub fn set_state_listener<T: From<SdkState> + Send + 'static>(&mut self, bind_addr: SocketAddr, listener: Sender<T>) -> Result<(), SdkError> {
+        // Release the port before binding it again
+        self.state_listener = None;
+        self.state_listener = Some(SdkStateListener::listen(bind_addr, listener)?);
+        Ok(())
+    }
+
+    // Turns on the video stream, which the drone sends as bare H.264 to port 11111
+    pub fn start_video(&mut self, bind_addr: SocketAddr, frame_channel: Sender<player::Frame>) -> Result<(), SdkError> {
+        self.video_stream = None;
+        let video_socket = UdpSocket::bind(bind_addr)?;
+        let video_stream = VideoStream::start(video_socket, 0, VIDEO_READ_TIMEOUT, frame_channel)?;
+        self.stream_on()?;
+        self.video_stream = Some(video_stream);
+        Ok(())
+    }
+
+    pub fn latest_state(&self) -> Option<SdkState> {
+        self.state_listener.as_ref().and_then(|listener| listener.latest())
+    }
+
+    pub fn takeoff(&self) -> Result<(), SdkError> {
+        self.command("takeoff")
+    }
+
+    pub fn land(&self) -> Result<(), SdkError> {
+        self.command("land")
+    }
+
+    // Stops all motors immediately, the drone drops out of the air. Like rc it bypasses the
+    // command lock and does not wait for the answer, so it is not held up by a running move.
+    // A command waiting for its answer meanwhile fails with SdkError::Interrupted.
+    pub fn emergency(&self) -> Result<(), SdkError> {
+        self.emergency_count.fetch_add(1, Ordering::SeqCst);
+        self.socket.send(b"emergency")?;
+        Ok(())
+    }
+
+    pub fn stream_on(&self) -> Result<(), SdkError> {
+        self.command("streamon")
+    }
+
+    pub fn stream_off(&self) -> Result<(), SdkError> {
+        self.command("streamoff")
+    }
+
+    pub fn up(&self, cm: i32) -> Result<(), SdkError> {
+        self.move_by("up", cm)
+    }
+
+    pub fn down(&self, cm: i32) -> Result<(), SdkError> {
+        self.move_by("down", cm)
+    }
+
+    pub fn left(&self, cm: i32) -> Result<(), SdkError> {
+        self.move_by("left", cm)
+    }
+
+    pub fn right(&self, cm: i32) -> Result<(), SdkError> {
+        self.move_by("right", cm)
+    }
+
+    pub fn forward(&self, cm: i32) -> Result<(), SdkError> {
+        self.move_by("forward", cm)
+    }
+
+    pub fn back(&self, cm: i32) -> Result<(), SdkError> {
+        self.move_by("back", cm)
+    }
+
+    pub fn clockwise(&self, degrees: i32) -> Result<(), SdkError> {
+        check_range("degrees", degrees, 1, 360)?;
+        self.command(&format!("cw {}", degrees))
+    }
+
+    pub fn counter_clockwise(&self, degrees: i32) -> Result<(), SdkError> {
+        check_range("degrees", degrees, 1, 360)?;
+        self.command(&format!("ccw {}", degrees))
+    }
+
+    pub fn flip(&self, direction: FlipDirection) -> Result<(), SdkError> {
+        let direction = match direction {
+            FlipDirection::Left => "l",
+            FlipDirection::Right => "r",
+            FlipDirection::Forward => "f",
+            FlipDirection::Backward => "b",
+            diagonal => return Err(SdkError::UnsupportedFlip(diagonal))
+        };
+        self.command(&format!("flip {}", direction))
+    }
+
+    // Flies to (x, y, z) relative to the current position, in cm and cm/s
+    pub fn go(&self, x: i32, y: i32, z: i32, speed: i32) -> Result<(), SdkError> {
+        for &(argument, value) in &[("x", x), ("y", y), ("z", z)] {
+            check_range(argument, value, -500, 500)?;
+        }
+        check_range("speed", speed, 10, 100)?;
+        self.command(&format!("go {} {} {} {}", x, y, z, speed))
+    }
+
+    // Flies a curve through the first point to the second, both relative to the current position
+    pub fn curve(&self, x1: i32, y1: i32, z1: i32, x2: i32, y2: i32, z2: i32, speed: i32) -> Result<(), SdkError> {
+        for &(argument, value) in &[("x1", x1), ("y1", y1), ("z1", z1), ("x2", x2), ("y2", y2), ("z2", z2)] {
+            check_range(argument, value, -500, 500)?;
+        }
+        check_range("speed", speed, 10, 60)?;
+        self.command(&format!("curve {} {} {} {} {} {} {}", x1, y1, z1, x2, y2, z2, speed))
+    }
+
+    pub fn set_speed(&self, cm_per_second: i32) -> Result<(), SdkError> {
+        check_range("speed", cm_per_second, 10, 100)?;
+        self.command(&format!("speed {}", cm_per_second))
+    }
+
+    // Stick input in the range -100 to 100. The drone does not answer rc commands.
+    pub fn rc(&self, left_right: i32, forward_back: i32, up_down: i32, yaw: i32) -> Result<(), SdkError> {
+        for &(argument, value) in &[("left_right", left_right), ("forward_back", forward_back), ("up_down", up_down), ("yaw", yaw)] {
+            check_range(argument, value, -100, 100)?;
+        }
+        let command = format!("rc {} {} {} {}", left_right, forward_back, up_down, yaw);
+        self.socket.send(command.as_bytes())?;
+        Ok(())
+    }
+
+    // Turns the drone into a station on another access point, it reboots afterwards
+    pub fn wifi(&self, ssid: &str, password: &str) -> Result<(), SdkError> {
+        self.command(&format!("wifi {} {}", ssid, password))
+    }
+
+    pub fn speed(&self) -> Result<f32, SdkError> {
+        self.query("speed?")
+    }
+
+    pub fn battery(&self) -> Result<u8, SdkError> {
+        self.query("battery?")
+    }
+
+    // Motor time in seconds
+    pub fn flight_time(&self) -> Result<u32, SdkError> {
+        self.query("time?")
+    }
+
+    // Height in dm
+    pub fn height(&self) -> Result<i32, SdkError> {
+        self.query("height?")
+    }
+
+    // Lowest and highest temperature in degrees Celsius
+    pub fn temperature(&self) -> Result<(i32, i32), SdkError> {
+        let command = "temp?";
+        let response = self.send_command(command)?;
+        let mut range = response.splitn(2, '~');
+        match (range.next(), range.next()) {
+            (Some(low), Some(high)) => Ok((parse_number(command, low)?, parse_number(command, high)?)),
+            (Some(temperature), None) => {
+                let temperature = parse_number(command, temperature)?;
+                Ok((temperature, temperature))
+            },
+            _ => Err(SdkError::InvalidResponse { command: String::from(command), response })
+        }
+    }
+
+    // Pitch, roll and yaw in degrees
+    pub fn attitude(&self) -> Result<(i32, i32, i32), SdkError> {
+        let command = "attitude?";
+        let response = self.send_command(command)?;
+        Ok((
+            parse_field(command, &response, "pitch")?,
+            parse_field(command, &response, "roll")?,
+            parse_field(command, &response, "yaw")?,
+        ))
+    }
+
+    // Barometer altitude in m
+    pub fn barometer(&self) -> Result<f32, SdkError> {
+        self.query("baro?")
+    }
+
+    // Acceleration along x, y and z in 0.001g
+    pub fn acceleration(&self) -> Result<(f32, f32, f32), SdkError> {
+        let command = "acceleration?";
+        let response = self.send_command(command)?;
+        Ok((
+            parse_field(command, &response, "agx")?,
+            parse_field(command, &response, "agy")?,
+            parse_field(command, &response, "agz")?,
+        ))
+    }
+
+    // Distance from the time of flight sensor in mm
+    pub fn tof(&self) -> Result<i32, SdkError> {
+        self.query("tof?")
+    }
+
+    // Wi-Fi signal to noise ratio
+    pub fn wifi_snr(&self) -> Result<i32, SdkError> {
+        self.query("wifi?")
+    }
+}
+
+// Scales a stick axis in [-1, 1] to the [-100, 100] the rc command expects
+fn rc_axis(value: f32) -> i32 {
+    (value * 100.0).round().clamp(-100.0, 100.0) as i32
+}
+
+impl DroneControl for SdkClient {
+    fn takeoff(&self) -> Result<(), DroneError> {
+        Ok(SdkClient::takeoff(self)?)
+    }
+
+    fn land(&self) -> Result<(), DroneError> {
+        Ok(SdkClient::land(self)?)
+    }
+
+    fn emergency(&self) -> Result<(), DroneError> {
+        Ok(SdkClient::emergency(self)?)
+    }
+
+    fn flip(&self, direction: FlipDirection) -> Result<(), DroneError> {
+        Ok(SdkClient::flip(self, direction)?)
+    }
+
+    // Same stick layout as the binary protocol: the left stick rolls and pitches, the right
+    // stick climbs and yaws, and pushing a stick away moves forward or up
+    fn set_sticks(&self, sticks: controller::State) -> Result<(), DroneError> {
+        Ok(self.rc(
+            rc_axis(sticks.joystick_left_x),
+            rc_axis(-sticks.joystick_left_y),
+            rc_axis(-sticks.joystick_right_y),
+            rc_axis(sticks.joystick_right_x)
+        )?)
+    }
+
+    fn set_telemetry_listener(&mut self, listener: Sender<Telemetry>) -> Result<(), DroneError> {
+        Ok(self.set_state_listener(SocketAddr::from(([0, 0, 0, 0], SDK_STATE_PORT)), listener)?)
+    }
+
+    fn start_video(&mut self, frame_channel: Sender<player::Frame>) -> Result<(), DroneError> {
+        Ok(SdkClient::start_video(self, SocketAddr::from(([0, 0, 0, 0], SDK_VIDEO_PORT)), frame_channel)?)
+    }
+}
+
+#[cfg(test)]
+fn sdk_stand_in(responses: &'static [(&'static str, &'static str)]) -> (SocketAddr, std::thread::JoinHandle<Vec<String>>) {
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let drone_addr = drone.local_addr().unwrap();
+
+    let stand_in = std::thread::spawn(move || {
+        let mut received = vec![];
+        let mut buffer = [0u8; 1024];
+        while let Ok((num_bytes, client)) = drone.recv_from(&mut buffer) {
+            let command = String::from_utf8_lossy(&buffer[..num_bytes]).to_string();
+            if let Some(&(_, response)) = responses.iter().find(|&&(expected, _)| expected == command) {
+                drone.send_to(response.as_bytes(), client).unwrap();
+            }
+            received.push(command);
+        }
+        received
+    });
+
+    (drone_addr, stand_in)
+}
+
+#[test]
+fn test_sdk_commands() {
+    let (drone_addr, stand_in) = sdk_stand_in(&[
+        ("command", "ok"),
+        ("takeoff", "ok"),
+        ("up 50", "ok"),
+        ("cw 90", "ok\r\n"),
+        ("go 100 -50 20 30", "ok"),
+        ("flip l", "ok"),
+        ("land", "error Not joystick"),
+    ]);
+
+    let client = SdkClient::connect(drone_addr, Duration::from_millis(200)).unwrap();
+    client.takeoff().unwrap();
+    client.up(50).unwrap();
+    client.clockwise(90).unwrap();
+    client.go(100, -50, 20, 30).unwrap();
+    client.flip(FlipDirection::Left).unwrap();
+    client.rc(10, -20, 0, 100).unwrap();
+    match client.land() {
+        Err(SdkError::Rejected { response, .. }) => assert_eq!(response, "error Not joystick"),
+        other => panic!("Expected the land command to be rejected, got {:?}", other),
+    }
+    drop(client);
+
+    assert_eq!(stand_in.join().unwrap(), vec![
+        "command", "takeoff", "up 50", "cw 90", "go 100 -50 20 30", "flip l", "rc 10 -20 0 100", "land"
+    ]);
+}
+
+#[test]
+fn test_sdk_argument_ranges() {
+    let (drone_addr, stand_in) = sdk_stand_in(&[("command", "ok")]);
+    let client = SdkClient::connect(drone_addr, Duration::from_millis(200)).unwrap();
+
+    assert!(matches!(client.forward(10), Err(SdkError::OutOfRange { argument: "distance", value: 10, .. })));
+    assert!(matches!(client.counter_clockwise(361), Err(SdkError::OutOfRange { .. })));
+    assert!(matches!(client.curve(0, 0, 0, 100, 100, 0, 61), Err(SdkError::OutOfRange { argument: "speed", .. })));
+    assert!(matches!(client.rc(0, 0, 101, 0), Err(SdkError::OutOfRange { argument: "up_down", .. })));
+    assert!(matches!(client.flip(FlipDirection::ForwardLeft), Err(SdkError::UnsupportedFlip(_))));
+    drop(client);
+
+    // Nothing out of range may reach the drone
+    assert_eq!(stand_in.join().unwrap(), vec!["command"]);
+}
+
+#[test]
+fn test_sdk_queries() {
+    let (drone_addr, _stand_in) = sdk_stand_in(&[
+        ("command", "ok"),
+        ("battery?", "87\r\n"),
+        ("speed?", "100.0"),
+        ("time?", "12s"),
+        ("height?", "10dm"),
+        ("temp?", "83~85C"),
+        ("attitude?", "pitch:-1;roll:2;yaw:-45;\r\n"),
+        ("baro?", "12.345"),
+        ("acceleration?", "agx:-6.00;agy:-2.00;agz:-998.00;"),
+        ("tof?", "100mm"),
+        ("wifi?", "garbage"),
+    ]);
+    let client = SdkClient::connect(drone_addr, Duration::from_millis(200)).unwrap();
+
+    assert_eq!(client.battery().unwrap(), 87);
+    assert_eq!(client.speed().unwrap(), 100.0);
+    assert_eq!(client.flight_time().unwrap(), 12);
+    assert_eq!(client.height().unwrap(), 10);
+    assert_eq!(client.temperature().unwrap(), (83, 85));
+    assert_eq!(client.attitude().unwrap(), (-1, 2, -45));
+    assert_eq!(client.barometer().unwrap(), 12.345);
+    assert_eq!(client.acceleration().unwrap(), (-6.0, -2.0, -998.0));
+    assert_eq!(client.tof().unwrap(), 100);
+    assert!(matches!(client.wifi_snr(), Err(SdkError::InvalidResponse { .. })));
+}
+
+#[test]
+fn test_sdk_timeout() {
+    let (drone_addr, _stand_in) = sdk_stand_in(&[("command", "ok")]);
+    let client = SdkClient::connect(drone_addr, Duration::from_millis(50)).unwrap();
+
+    match client.takeoff() {
+        Err(SdkError::Timeout(command)) => assert_eq!(command, "takeoff"),
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_sdk_drone_control() {
+    let (drone_addr, stand_in) = sdk_stand_in(&[
+        ("command", "ok"),
+        ("takeoff", "ok"),
+        ("emergency", "ok"),
+    ]);
+    let mut client = SdkClient::connect(drone_addr, Duration::from_millis(200)).unwrap();
+
+    let (telemetry_sender, telemetry_receiver) = std::sync::mpsc::channel();
+    client.set_state_listener("127.0.0.1:0".parse().unwrap(), telemetry_sender).unwrap();
+    let state_addr = client.state_listener.as_ref().unwrap().local_addr();
+    UdpSocket::bind("127.0.0.1:0").unwrap().send_to(
+        b"pitch:0;roll:0;yaw:0;vgx:0;vgy:0;vgz:0;templ:83;temph:85;tof:10;h:0;bat:42;baro:1.00;time:0;agx:0.00;agy:0.00;agz:-1000.00;",
+        state_addr
+    ).unwrap();
+    match telemetry_receiver.recv_timeout(Duration::from_secs(2)) {
+        Ok(Telemetry::SdkState(state)) => assert_eq!(state.battery, 42),
+        other => panic!("Expected SDK state telemetry, got {:?}", other),
+    }
+    assert_eq!(client.latest_state().map(|state| state.battery), Some(42));
+
+    let mission: &dyn DroneControl = &client;
+    mission.takeoff().unwrap();
+    mission.set_sticks(controller::State {
+        joystick_left_x: 0.25,
+        joystick_left_y: -1.0,
+        joystick_right_x: -0.5,
+        joystick_right_y: 1.5
+    }).unwrap();
+    mission.emergency().unwrap();
+    drop(client);
+
+    assert_eq!(stand_in.join().unwrap(), vec!["command", "takeoff", "rc 25 100 -100 -50", "emergency"]);
+}
+
+#[test]
+fn test_sdk_emergency_during_move() {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    // The move is never answered, so it blocks until its timeout
+    let (drone_addr, stand_in) = sdk_stand_in(&[("command", "ok"), ("emergency", "ok")]);
+    let client = Arc::new(SdkClient::connect(drone_addr, Duration::from_secs(1)).unwrap());
+
+    let moving_client = client.clone();
+    let move_thread = std::thread::spawn(move || moving_client.forward(500));
+    std::thread::sleep(Duration::from_millis(100));
+
+    let started = Instant::now();
+    client.emergency().unwrap();
+    assert!(started.elapsed() < Duration::from_millis(100));
+
+    // The answer to the emergency stop must not pass for the move having completed
+    match move_thread.join().unwrap() {
+        Err(SdkError::Interrupted(command)) => assert_eq!(command, "forward 500"),
+        other => panic!("Expected the move to be interrupted, got {:?}", other),
+    }
+    drop(client);
+    assert_eq!(stand_in.join().unwrap(), vec!["command", "forward 500", "emergency"]);
+}
+
+#[test]
+fn test_sticks_match_binary_protocol() {
+    use crate::protocol::TelloGram;
+    use crate::tello::{ connect_to_stand_in, TelloConfig };
+
+    let sticks = controller::State { joystick_left_x: 0.25, joystick_left_y: -0.5, joystick_right_x: -0.75, joystick_right_y: 1.0 };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let (tello, _) = connect_to_stand_in(&drone, TelloConfig::new().video_port(9054));
+    DroneControl::set_sticks(&tello, sticks).unwrap();
+    let mut buffer = [0u8; 64];
+    let binary_axes = loop {
+        let num_bytes = drone.recv(&mut buffer).unwrap();
+        match TelloGram::parse(&buffer[..num_bytes]) {
+            Ok(gram) if gram.id() == 0x50 => break TelloGram::joystick_axes(gram.payload()).unwrap(),
+            _ => ()
+        }
+    };
+    drop(tello);
+
+    let (drone_addr, stand_in) = sdk_stand_in(&[("command", "ok")]);
+    let client = SdkClient::connect(drone_addr, Duration::from_millis(200)).unwrap();
+    DroneControl::set_sticks(&client, sticks).unwrap();
+    drop(client);
+    let received = stand_in.join().unwrap();
+    let sdk_axes: Vec<f32> = received[1].split(' ').skip(1).map(|axis| axis.parse::<f32>().unwrap() / 100.0).collect();
+
+    // Roll, pitch, throttle and yaw, in the order of both protocols
+    assert_eq!(sdk_axes, vec![0.25, 0.5, -1.0, -0.75]);
+    for (binary, sdk) in binary_axes.iter().zip(&sdk_axes) {
+        assert!((binary - sdk).abs() < 0.01, "Binary axes {:?}, SDK axes {:?}", binary_axes, sdk_axes);
+    }
+}
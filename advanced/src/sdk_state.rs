@@ -0,0 +1,249 @@
+use std::io;
+use std::collections::HashMap;
+use std::net::{ SocketAddr, UdpSocket };
+use std::str::FromStr;
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+pub const SDK_STATE_PORT: u16 = 8890;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MissionPad {
+    pub id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+// One "key:value;" line broadcast by the drone in SDK mode. Angles are in degrees,
+// velocities in dm/s, distances in cm, barometer in m, accelerations in 0.001g and the
+// temperatures exactly as reported by the drone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SdkState {
+    pub pitch: i32,
+    pub roll: i32,
+    pub yaw: i32,
+
+    pub velocity_x: i32,
+    pub velocity_y: i32,
+    pub velocity_z: i32,
+
+    pub temperature_low: i32,
+    pub temperature_high: i32,
+
+    pub tof: i32,
+    pub height: i32,
+    pub battery: i32,
+    pub barometer: f32,
+    pub flight_time: i32,
+
+    pub acceleration_x: f32,
+    pub acceleration_y: f32,
+    pub acceleration_z: f32,
+
+    pub mission_pad: Option<MissionPad>,
+}
+
+impl SdkState {
+    pub fn parse(state: &str) -> Option<SdkState> {
+        let mut fields = HashMap::new();
+        for field in state.trim().split(';').filter(|field| !field.is_empty()) {
+            let mut parts = field.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => fields.insert(key.trim(), value.trim()),
+                _ => return None
+            };
+        }
+
+        fn value<T: FromStr>(fields: &HashMap<&str, &str>, key: &str) -> Option<T> {
+            fields.get(key)?.parse().ok()
+        }
+
+        // Mission pad fields are only sent with pad detection enabled, an id of -1 means no pad in sight
+        let mission_pad = match value::<i32>(&fields, "mid") {
+            Some(id) if id >= 0 => Some(MissionPad {
+                id,
+                x: value(&fields, "x")?,
+                y: value(&fields, "y")?,
+                z: value(&fields, "z")?,
+            }),
+            Some(_) => None,
+            None if fields.contains_key("mid") => return None,
+            None => None
+        };
+
+        Some(SdkState {
+            pitch: value(&fields, "pitch")?,
+            roll: value(&fields, "roll")?,
+            yaw: value(&fields, "yaw")?,
+
+            velocity_x: value(&fields, "vgx")?,
+            velocity_y: value(&fields, "vgy")?,
+            velocity_z: value(&fields, "vgz")?,
+
+            temperature_low: value(&fields, "templ")?,
+            temperature_high: value(&fields, "temph")?,
+
+            tof: value(&fields, "tof")?,
+            height: value(&fields, "h")?,
+            battery: value(&fields, "bat")?,
+            barometer: value(&fields, "baro")?,
+            flight_time: value(&fields, "time")?,
+
+            acceleration_x: value(&fields, "agx")?,
+            acceleration_y: value(&fields, "agy")?,
+            acceleration_z: value(&fields, "agz")?,
+
+            mission_pad,
+        })
+    }
+}
+
+// Listens for state broadcasts on the SDK state port, keeping the latest state around
+// and forwarding every update to the listener, converted into whatever it listens for
+pub struct SdkStateListener {
+    is_running: Arc<AtomicBool>,
+    latest: Arc<Mutex<Option<SdkState>>>,
+    local_addr: SocketAddr,
+    listen_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl SdkStateListener {
+    pub fn listen<T: From<SdkState> + Send + 'static>(bind_addr: SocketAddr, listener: Sender<T>) -> io::Result<SdkStateListener> {
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        let local_addr = socket.local_addr()?;
+
+        let is_running = Arc::new(AtomicBool::new(true));
+        let latest = Arc::new(Mutex::new(None));
+
+        let is_running_listen = is_running.clone();
+        let latest_listen = latest.clone();
+        let listen_thread = Some(thread::spawn(move || {
+            let mut buffer = [0u8; 1024];
+            let mut listener = Some(listener);
+            while (*is_running_listen).load(Ordering::Relaxed) {
+                let num_bytes = match socket.recv(&mut buffer) {
+                    Ok(num_bytes) => num_bytes,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => continue,
+                    Err(e) => {
+                        println!("Failed to receive SDK state: {}", e);
+                        continue;
+                    }
+                };
+
+                let raw_state = String::from_utf8_lossy(&buffer[..num_bytes]);
+                let state = match SdkState::parse(&raw_state) {
+                    Some(state) => state,
+                    None => {
+                        println!("Received malformed SDK state {:?}", raw_state);
+                        continue;
+                    }
+                };
+
+                *latest_listen.lock().unwrap() = Some(state);
+                if let Some(sender) = &listener {
+                    if sender.send(T::from(state)).is_err() {
+                        listener = None;
+                    }
+                }
+            }
+        }));
+
+        Ok(SdkStateListener {
+            is_running,
+            latest,
+            local_addr,
+            listen_thread,
+        })
+    }
+
+    pub fn latest(&self) -> Option<SdkState> {
+        *self.latest.lock().unwrap()
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for SdkStateListener {
+    fn drop(&mut self) {
+        self.is_running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.listen_thread.take() {
+            if thread.join().is_err() {
+                println!("SDK state listener thread panicked");
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parse_sdk_state() {
+    let state = SdkState::parse(
+        "pitch:-1;roll:2;yaw:-45;vgx:0;vgy:1;vgz:-2;templ:83;temph:85;tof:10;h:0;bat:87;baro:-32.58;time:12;agx:-6.00;agy:-2.00;agz:-998.00;\r\n"
+    ).unwrap();
+
+    assert_eq!(state.pitch, -1);
+    assert_eq!(state.roll, 2);
+    assert_eq!(state.yaw, -45);
+    assert_eq!((state.velocity_x, state.velocity_y, state.velocity_z), (0, 1, -2));
+    assert_eq!((state.temperature_low, state.temperature_high), (83, 85));
+    assert_eq!(state.tof, 10);
+    assert_eq!(state.height, 0);
+    assert_eq!(state.battery, 87);
+    assert_eq!(state.barometer, -32.58);
+    assert_eq!(state.flight_time, 12);
+    assert_eq!((state.acceleration_x, state.acceleration_y, state.acceleration_z), (-6.0, -2.0, -998.0));
+    assert_eq!(state.mission_pad, None);
+}
+
+#[test]
+fn test_parse_sdk_state_with_mission_pad() {
+    let base = "pitch:0;roll:0;yaw:0;vgx:0;vgy:0;vgz:0;templ:60;temph:62;tof:52;h:40;bat:70;baro:10.5;time:3;agx:1.00;agy:2.00;agz:-1000.00;";
+
+    let detected = SdkState::parse(&format!("mid:3;x:-20;y:15;z:40;mpry:0,0,0;{}", base)).unwrap();
+    assert_eq!(detected.mission_pad, Some(MissionPad { id: 3, x: -20, y: 15, z: 40 }));
+    assert_eq!(detected.height, 40);
+
+    let not_detected = SdkState::parse(&format!("mid:-1;x:-100;y:-100;z:-100;mpry:0,0,0;{}", base)).unwrap();
+    assert_eq!(not_detected.mission_pad, None);
+
+    assert_eq!(SdkState::parse(&format!("mid:3;x:-20;{}", base)), None);
+}
+
+#[test]
+fn test_parse_malformed_sdk_state() {
+    let valid = "pitch:0;roll:0;yaw:0;vgx:0;vgy:0;vgz:0;templ:83;temph:85;tof:10;h:0;bat:87;baro:-32.58;time:0;agx:-6.00;agy:-2.00;agz:-998.00;";
+    assert!(SdkState::parse(valid).is_some());
+
+    assert_eq!(SdkState::parse(""), None);
+    assert_eq!(SdkState::parse("ok"), None);
+    assert_eq!(SdkState::parse(&valid[..valid.len() / 2]), None);
+    assert_eq!(SdkState::parse(&valid.replace("bat:87", "bat:eighty")), None);
+    assert_eq!(SdkState::parse(&valid.replace("pitch:0;", "pitch0;")), None);
+    assert_eq!(SdkState::parse(&valid.replace("h:0;", "")), None);
+}
+
+#[test]
+fn test_sdk_state_listener() {
+    let (sender, receiver) = std::sync::mpsc::channel::<SdkState>();
+    let listener = SdkStateListener::listen("127.0.0.1:0".parse().unwrap(), sender).unwrap();
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.send_to(b"garbage", listener.local_addr()).unwrap();
+    drone.send_to(
+        b"pitch:0;roll:0;yaw:90;vgx:0;vgy:0;vgz:0;templ:83;temph:85;tof:10;h:0;bat:55;baro:1.00;time:0;agx:0.00;agy:0.00;agz:-1000.00;",
+        listener.local_addr()
+    ).unwrap();
+
+    let state = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(state.yaw, 90);
+    assert_eq!(state.battery, 55);
+    assert_eq!(listener.latest(), Some(state));
+}
@@ -1,325 +1,450 @@
-mod crc;
-mod player;
-mod controller;
-
-extern crate gstreamer as gst;
-extern crate gstreamer_app as gst_app;
-
-use gst::prelude::*;
-
-use std::net::{ SocketAddr, UdpSocket };
-use std::convert::TryInto;
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::error::Error;
+use std::net::{ IpAddr, SocketAddr, UdpSocket };
+use std::path::Path;
 use std::thread;
-use std::time;
 use std::sync::{ Arc, Mutex, Condvar };
 use std::sync::atomic::{ AtomicBool, AtomicU16, Ordering };
-use std::slice;
-use std::assert;
-use std::time::Duration;
-use chrono::{ Utc, Timelike };
+use std::time::{ Duration, Instant };
 
-use std::sync::mpsc::{ channel, Receiver, Sender };
+use std::sync::mpsc::{ channel, Receiver, Sender, TryRecvError };
 
-const TELLO_CMD_PORT: u16 = 8889;
-const LOCAL_CMD_PORT: u16 = 8800;
-const VIDEO_PORT: u16 = 8040;
-const TELLO_IP: [u8; 4] = [ 192, 168, 10, 1 ];
+use crate::controller;
+use crate::drone::{ DroneControl, DroneError };
+use crate::player;
+use crate::sdk_state::SdkState;
+use crate::video::{ VideoError, VideoStream };
+use crate::flight_data::FlightData;
+use crate::log_data::{ self, LogRecord, MvoRecord, ImuRecord };
+use crate::file_transfer::{ FileAssembler, FileChunk, FileInfo, CHUNKS_PER_PIECE };
+use crate::protocol::{ TelloGram, ParseError, Commands, FlipDirection, SpeedMode, VideoBitrate, VideoMode, Exposure, TelloConnectRequest, NetworkPackage };
 
-#[repr(packed(1))]
-struct TelloGram {
-    m_header: u8,
-    m_size: u16,
-    m_crc8: u8,
-    m_discriminator: u8,
-    m_id: u16,
-    m_sequence: u16
-}
+pub const TELLO_CMD_PORT: u16 = 8889;
+pub const LOCAL_CMD_PORT: u16 = 8800;
+pub const VIDEO_PORT: u16 = 8040;
+pub const TELLO_IP: [u8; 4] = [ 192, 168, 10, 1 ];
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(50);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(8);
 
 #[derive(Debug)]
-struct FlightData {
-    height: u16,
-    battery_percentage: u8,
-    camera_state: u8
+pub enum TelloError {
+    Network(io::Error),
+    ConnectTimeout,
+    NoAck { id: u16, sequence: u16 },
+    NoEmergencyAck,
+    Rejected { id: u16, code: u8 },
+    InvalidResponse { id: u16, payload: Vec<u8> },
+    Protocol(ParseError),
+    FileTransferStalled,
+    VideoNotStarted,
+    Video(VideoError),
 }
 
-impl FlightData {
-    fn from(bytes: &[u8]) -> FlightData {
-        assert!(bytes.len() == 24);
-
-        FlightData {
-            height: (bytes[0] as u16) | ((bytes[1] as u16) << 8),
-            battery_percentage: bytes[12],
-            camera_state: bytes[20]
+impl fmt::Display for TelloError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TelloError::Network(e) => write!(f, "Network error talking to Tello: {}", e),
+            TelloError::ConnectTimeout => write!(f, "Timed out connecting to Tello"),
+            TelloError::NoAck { id, sequence } => write!(f, "Tello did not acknowledge command {:#x} with sequence {}", id, sequence),
+            TelloError::NoEmergencyAck => write!(f, "Tello did not acknowledge the emergency stop before shutting down"),
+            TelloError::Rejected { id, code } => write!(f, "Tello rejected command {:#x} with code {}", id, code),
+            TelloError::InvalidResponse { id, payload } => write!(f, "Invalid response to command {:#x}: {:?}", id, payload),
+            TelloError::Protocol(e) => write!(f, "Invalid TelloGram: {}", e),
+            TelloError::FileTransferStalled => write!(f, "Tello stopped sending the file before it was complete"),
+            TelloError::VideoNotStarted => write!(f, "Video has not been started"),
+            TelloError::Video(e) => write!(f, "Video error: {}", e),
         }
     }
 }
 
-#[derive(Debug)]
-enum TelloGramDirection {
-    ToDrone, FromDrone, Unknown
+impl Error for TelloError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TelloError::Network(e) => Some(e),
+            TelloError::Video(e) => Some(e),
+            TelloError::Protocol(e) => Some(e),
+            TelloError::ConnectTimeout |
+            TelloError::NoAck { .. } |
+            TelloError::NoEmergencyAck |
+            TelloError::Rejected { .. } |
+            TelloError::InvalidResponse { .. } |
+            TelloError::FileTransferStalled |
+            TelloError::VideoNotStarted => None,
+        }
+    }
 }
 
-#[derive(Debug)]
-enum FlipDirection {
-    Forward,
-    Left,
-    Backward,
-    Right,
-    ForwardLeft,
-    BackwardLeft,
-    BackwardRight,
-    ForwardRight
+impl From<io::Error> for TelloError {
+    fn from(e: io::Error) -> TelloError {
+        TelloError::Network(e)
+    }
 }
 
-#[derive(Debug)]
-enum Commands {
-    VideoSPSPPS,
-    Takeoff,
-    Land,
-    Joystick { lx: f32, ly: f32, rx: f32, ry: f32 },
-    Flip(FlipDirection)
+impl From<VideoError> for TelloError {
+    fn from(e: VideoError) -> TelloError {
+        TelloError::Video(e)
+    }
 }
 
-enum PackageType {
-    Get,
-    Set,
-    Data2,
-    Flip
+#[derive(Clone, Debug)]
+pub struct TelloConfig {
+    drone_ip: IpAddr,
+    remote_cmd_port: u16,
+    local_cmd_port: u16,
+    video_port: u16,
+    bind_ip: IpAddr,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    heartbeat_timeout: Duration,
+    reconnect_backoff: Duration,
+    ack_timeout: Duration,
+    ack_retries: u32,
+    file_transfer_timeout: Duration,
 }
 
-impl PackageType {
-    fn to_u8(&self) -> u8 {
-        match self {
-            Get => 1,
-            Data2 => 4,
-            Set => 5,
-            Flip => 6
+impl Default for TelloConfig {
+    fn default() -> TelloConfig {
+        TelloConfig {
+            drone_ip: IpAddr::from(TELLO_IP),
+            remote_cmd_port: TELLO_CMD_PORT,
+            local_cmd_port: LOCAL_CMD_PORT,
+            video_port: VIDEO_PORT,
+            bind_ip: IpAddr::from([0, 0, 0, 0]),
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(1),
+            heartbeat_timeout: Duration::from_secs(3),
+            reconnect_backoff: Duration::from_millis(500),
+            ack_timeout: Duration::from_millis(500),
+            ack_retries: 3,
+            file_transfer_timeout: Duration::from_secs(5),
         }
     }
 }
 
-impl TelloGram {
-    const GRAM_SIZE: usize = 11;
-
-    fn header(&self) -> u8 {
-        self.m_header
+// Local ports may be set to 0 to let the OS pick a free one, except for the video port
+// which has to be known up front since it is sent to the drone in the connect request.
+impl TelloConfig {
+    pub fn new() -> TelloConfig {
+        TelloConfig::default()
     }
 
-    fn size(&self) -> usize {
-        (self.m_size >> 3) as usize
+    pub fn drone_ip<T: Into<IpAddr>>(mut self, drone_ip: T) -> TelloConfig {
+        self.drone_ip = drone_ip.into();
+        self
     }
 
-    fn crc8(&self) -> u8 {
-        self.m_crc8
+    pub fn remote_cmd_port(mut self, port: u16) -> TelloConfig {
+        self.remote_cmd_port = port;
+        self
     }
 
-    fn packet_direction(&self) -> TelloGramDirection {
-        match self.m_discriminator {
-            val if (val & 0x80) != 0 => TelloGramDirection::FromDrone,
-            val if (val & 0x40) != 0 => TelloGramDirection::ToDrone,
-            _ => TelloGramDirection::Unknown
-        }
+    pub fn local_cmd_port(mut self, port: u16) -> TelloConfig {
+        self.local_cmd_port = port;
+        self
     }
 
-    fn packet_type(&self) -> u8 {
-        (self.m_discriminator >> 3) & 0x7
+    pub fn video_port(mut self, port: u16) -> TelloConfig {
+        self.video_port = port;
+        self
     }
 
-    fn packet_subtype(&self) -> u8 {
-        self.m_discriminator & 0x7
+    pub fn bind_ip<T: Into<IpAddr>>(mut self, bind_ip: T) -> TelloConfig {
+        self.bind_ip = bind_ip.into();
+        self
     }
 
-    fn id(&self) -> u16 {
-        self.m_id
+    pub fn connect_timeout(mut self, timeout: Duration) -> TelloConfig {
+        self.connect_timeout = timeout;
+        self
     }
 
-    fn sequence(&self) -> u16 {
-        self.m_sequence
+    pub fn read_timeout(mut self, timeout: Duration) -> TelloConfig {
+        self.read_timeout = timeout;
+        self
     }
 
-    fn payload(&self) -> Vec<u8> {
-        let payload_size = self.size() - TelloGram::GRAM_SIZE;
-        unsafe {
-            let gram_start = (self as *const TelloGram) as *const u8;
-            slice::from_raw_parts(gram_start.offset(9), payload_size)
-        }.to_vec()
+    // The drone streams flight data several times a second, so hearing nothing for
+    // this long means the link is gone
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> TelloConfig {
+        self.heartbeat_timeout = timeout;
+        self
     }
 
-    fn crc16(&self) -> u16 {
-        unsafe {
-            let crc16_offset = (self.size() as isize) - 2;
-            let crc16_start = ((self as *const TelloGram) as *const u8).offset(crc16_offset);
-            let mut res: u16 = *(crc16_start.offset(1)) as u16;
-            res = res << 8;
-            res |= *crc16_start as u16;
-            res
-        }
+    // Delay before the second reconnect attempt, doubled for every further attempt
+    pub fn reconnect_backoff(mut self, backoff: Duration) -> TelloConfig {
+        self.reconnect_backoff = backoff;
+        self
     }
 
-    fn is_valid(&self) -> bool {
-        let header_slice = unsafe {
-            let gram_start = (self as *const TelloGram) as *const u8;
-            slice::from_raw_parts(gram_start, 3)
-        };
-        let payload_slice = unsafe {
-            let gram_start = (self as *const TelloGram) as *const u8;
-            slice::from_raw_parts(gram_start, self.size() - 2)
-        };
-        return crc::calculate_crc8(header_slice) == self.crc8()
-            && crc::calculate_crc16(payload_slice) == self.crc16();
+    pub fn ack_timeout(mut self, timeout: Duration) -> TelloConfig {
+        self.ack_timeout = timeout;
+        self
     }
 
-    fn construct_package(packet_type: PackageType, command: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
-        let packet_size = TelloGram::GRAM_SIZE + payload.len();
-
-        let mut buffer = vec![0; packet_size];
-        let mut gram = unsafe { &mut *buffer.as_mut_ptr().cast::<TelloGram>() };
-        gram.m_header = 0xcc;
-        gram.m_size = (packet_size << 3) as u16;
-        gram.m_crc8 = crc::calculate_crc8(&buffer[..3]);
-        gram.m_discriminator |= 0x40;
-        gram.m_discriminator |= (packet_type.to_u8() << 3) & 0x38;
-        // gram.m_discriminator |= packet_subtype & 0x7;
-        gram.m_id = command;
-        gram.m_sequence = seq;
-
-        for i in 0..payload.len() {
-            buffer[i + 9] = payload[i];
-        }
-
-        let crc16 = crc::calculate_crc16(&buffer[..packet_size - 2]);
-        let crc16_buf: [u8; 2] = unsafe { std::mem::transmute(crc16.to_be()) };
-        buffer[packet_size - 2] = crc16_buf[1];
-        buffer[packet_size - 1] = crc16_buf[0];
-
-        buffer
+    // Number of times a command is re-sent when the drone does not acknowledge it in time
+    pub fn ack_retries(mut self, retries: u32) -> TelloConfig {
+        self.ack_retries = retries;
+        self
     }
 
-    fn tello_position(position: f32) -> u64 {
-        (1024f32 + (position * 660f32)) as u64
+    // Downloads are abandoned once no new chunk arrived for this long
+    pub fn file_transfer_timeout(mut self, timeout: Duration) -> TelloConfig {
+        self.file_transfer_timeout = timeout;
+        self
     }
 
-    fn from(command: Commands, seq: u16) -> Vec<u8> {
-        match command {
-            Commands::VideoSPSPPS => TelloGram::construct_package(PackageType::Data2, 0x25, seq, &[]),
-            Commands::Takeoff => TelloGram::construct_package(PackageType::Set, 0x54, seq, &[]),
-            Commands::Land => TelloGram::construct_package(PackageType::Set, 0x55, seq, &vec![0]),
-            Commands::Joystick { lx, ly, rx, ry } => {
-                let mut encoded_position = Self::tello_position(lx) & 0x7ff;
-                encoded_position |= (Self::tello_position(-ly) & 0x7ff) << 11;
-                encoded_position |= (Self::tello_position(-ry) & 0x7ff) << 22;
-                encoded_position |= (Self::tello_position(rx) & 0x7ff) << 33;
-                // encoded_position |= 1u64 << 44; // if sports mode enabled
-
-                let mut payload = [0u8; 11];
-                for i in 0..6 {
-                    payload[i] = (encoded_position >> (8 * i)) as u8;
-                }
+    pub fn drone_cmd_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.drone_ip, self.remote_cmd_port)
+    }
 
-                let now = Utc::now();
-                payload[6] = now.hour() as u8;
-                payload[7] = now.minute() as u8;
-                payload[8] = now.second() as u8;
-                let ms = now.nanosecond() / 1_000_000;
-                payload[9] = ms as u8;
-                payload[10] = (ms >> 8) as u8;
+    pub fn local_cmd_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_ip, self.local_cmd_port)
+    }
 
-                TelloGram::construct_package(PackageType::Data2, 0x50, 0, &payload)
-            },
-            Commands::Flip(direction) => {
-                TelloGram::construct_package(PackageType::Flip, 0x5c, seq, &[direction as u8])
-            },
-        }
+    pub fn local_video_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_ip, self.video_port)
     }
 }
 
-trait NetworkPackage {
-    fn as_bytes(&self) -> Vec<u8>;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WifiStrength {
+    pub strength: u8,
+    pub interference: u8,
 }
 
-struct TelloConnectRequest<'a> {
-    cmd: &'a str,
-    video_port: u16
+#[derive(Clone, Debug)]
+pub enum Telemetry {
+    FlightData(FlightData),
+    Mvo(MvoRecord),
+    Imu(ImuRecord),
+    Disconnected,
+    Reconnected,
+    SpeedMode(SpeedMode),
+    // The drone reports its Wi-Fi link on its own, versions are published when queried
+    Wifi(WifiStrength),
+    FirmwareVersion(String),
+    LoaderVersion(String),
+    // Packages from the drone which could not be parsed, and were dropped
+    InvalidPackage(ParseError),
+    // Only published by the text SDK, which reports its state as a single line
+    SdkState(SdkState),
 }
 
-impl<'a> TelloConnectRequest<'a> {
-    fn connect(video_port: u16) -> TelloConnectRequest<'a> {
-        TelloConnectRequest {
-            cmd: "conn_req",
-            video_port
-        }
+impl From<SdkState> for Telemetry {
+    fn from(state: SdkState) -> Telemetry {
+        Telemetry::SdkState(state)
     }
 }
 
-impl<'a> NetworkPackage for TelloConnectRequest<'a> {
-    fn as_bytes(&self) -> Vec<u8> {
-        const COLON: u8 = ':' as u8;
-        let command_bytes = self.cmd.as_bytes();
-        let mut bytes: Vec<u8> = Vec::with_capacity(command_bytes.len() + 3);
-
-        for byte in command_bytes { bytes.push(*byte) }
-        bytes.push(COLON);
-        bytes.push((self.video_port & 0xff).try_into().expect("Wtf"));
-        bytes.push((self.video_port >> 8).try_into().expect("Wtf"));
-        
-        bytes
+impl Telemetry {
+    fn from_log_record(record: LogRecord) -> Telemetry {
+        match record {
+            LogRecord::Mvo(mvo) => Telemetry::Mvo(mvo),
+            LogRecord::Imu(imu) => Telemetry::Imu(imu),
+        }
     }
 }
 
 struct State {
     is_connected: bool,
+    last_seen: Option<Instant>,
+    disconnected_at: Option<Instant>,
     is_flying: bool,
+    flight_data: Option<FlightData>,
+    light_strength: Option<u8>,
+    speed_mode: SpeedMode,
+    video_mode: VideoMode,
+    max_height: Option<u16>,
+    low_battery_threshold: Option<u8>,
+    attitude_angle: Option<f32>,
+    firmware_version: Option<String>,
+    loader_version: Option<String>,
+    wifi_strength: Option<WifiStrength>,
+    telemetry_listener: Option<Sender<Telemetry>>,
+    pending_acks: HashMap<(u16, u16), Sender<Vec<u8>>>,
+    text_acks: Vec<Sender<()>>,
+    file_transfer: Option<FileAssembler>,
+    picture_listener: Option<Sender<Vec<u8>>>,
 }
 
 impl State {
     fn new() -> State {
         State {
             is_connected: false,
+            last_seen: None,
+            disconnected_at: None,
             is_flying: false,
+            flight_data: None,
+            light_strength: None,
+            speed_mode: SpeedMode::Slow,
+            video_mode: VideoMode::Normal,
+            max_height: None,
+            low_battery_threshold: None,
+            attitude_angle: None,
+            firmware_version: None,
+            loader_version: None,
+            wifi_strength: None,
+            telemetry_listener: None,
+            pending_acks: HashMap::new(),
+            text_acks: vec![],
+            file_transfer: None,
+            picture_listener: None,
+        }
+    }
+
+    fn publish(&mut self, telemetry: Telemetry) {
+        if let Some(listener) = &self.telemetry_listener {
+            if listener.send(telemetry).is_err() {
+                self.telemetry_listener = None;
+            }
         }
     }
+
+    // Left to the telemetry listener, printing every dropped package would flood the output
+    fn report_invalid(&mut self, error: ParseError) {
+        self.publish(Telemetry::InvalidPackage(error));
+    }
 }
 
-struct Tello {
+// Resolves once the drone acknowledges a command with the same id and sequence number,
+// or fails after the configured number of retries went unanswered
+pub struct CommandHandle {
+    id: u16,
+    sequence: u16,
+    result: Receiver<Result<Vec<u8>, TelloError>>,
+}
+
+impl CommandHandle {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    pub fn wait(self) -> Result<(), TelloError> {
+        self.response().map(|_| ())
+    }
+
+    pub fn try_wait(&self) -> Option<Result<(), TelloError>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result.map(|_| ())),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(TelloError::NoAck { id: self.id, sequence: self.sequence })),
+        }
+    }
+
+    // Waits for the payload of the ack, which carries the result of queries and settings
+    fn response(self) -> Result<Vec<u8>, TelloError> {
+        let (id, sequence) = (self.id, self.sequence);
+        self.result.recv().unwrap_or(Err(TelloError::NoAck { id, sequence }))
+    }
+}
+
+// Setting acks and query responses start with a result code, which is 0 on success
+fn check_result_code(id: u16, payload: &[u8]) -> Result<(), TelloError> {
+    match payload.first() {
+        Some(0) => Ok(()),
+        Some(&code) => Err(TelloError::Rejected { id, code }),
+        None => Err(TelloError::InvalidResponse { id, payload: payload.to_vec() }),
+    }
+}
+
+fn truncated(gram: &TelloGram) -> ParseError {
+    ParseError::TruncatedPayload { id: gram.id(), length: gram.payload().len() }
+}
+
+// Version strings are padded with NUL bytes
+fn decode_version(payload: &[u8]) -> Option<String> {
+    let version = String::from_utf8_lossy(response_value(payload)?);
+    Some(version.trim_end_matches('\0').trim().to_string())
+}
+
+// The value of a successful query response follows its result code
+fn response_value(payload: &[u8]) -> Option<&[u8]> {
+    match payload {
+        [0, value @ ..] => Some(value),
+        _ => None
+    }
+}
+
+// Resolves once the drone acknowledges the emergency stop, which is re-sent until it does
+pub struct EmergencyHandle {
+    result: Receiver<Result<(), TelloError>>,
+}
+
+impl EmergencyHandle {
+    pub fn wait(self) -> Result<(), TelloError> {
+        self.result.recv().unwrap_or(Err(TelloError::NoEmergencyAck))
+    }
+
+    pub fn try_wait(&self) -> Option<Result<(), TelloError>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(TelloError::NoEmergencyAck)),
+        }
+    }
+}
+
+pub struct Tello {
+    config: TelloConfig,
     state: Arc<Mutex<State>>,
 
     is_running: Arc<AtomicBool>,
 
     cmd_listen_thread: Option<thread::JoinHandle<()>>,
+    connection_watchdog_thread: Option<thread::JoinHandle<()>>,
     cmd_queue: UdpSocket,
     seq_nr: Arc<AtomicU16>,
+    picture_lock: Mutex<()>,
 
-    video_raw_receive_thread: Option<thread::JoinHandle<()>>,
-    video_frame_thread: Option<thread::JoinHandle<()>>,
+    video_stream: Option<VideoStream>,
     video_ping_thread: Option<thread::JoinHandle<()>>,
 }
 
 macro_rules! join_thread {
     ($x:expr) => {
         if let Some(thread) = $x.take() {
-            thread.join().unwrap();
+            if thread.join().is_err() {
+                println!("Tello worker thread panicked");
+            }
         }
     };
 }
 
 impl Drop for Tello {
     fn drop(&mut self) {
-        join_thread!(self.cmd_listen_thread);
+        if let Err(e) = self.shutdown() {
+            println!("Failed to shut down Tello cleanly: {}", e);
+        }
+    }
+}
 
-        join_thread!(self.video_raw_receive_thread);
-        join_thread!(self.video_frame_thread);
-        join_thread!(self.video_ping_thread);
+// Sleeps in small steps so worker threads notice a shutdown within a bounded time
+fn sleep_while_running(is_running: &AtomicBool, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while is_running.load(Ordering::Relaxed) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            break;
+        }
+        thread::sleep(cmp::min(remaining, WATCHDOG_INTERVAL));
     }
 }
 
 impl Tello {
-    fn connect(video_port: u16) -> Result<Tello, &'static str> {
-        let cmd_bind_addr = SocketAddr::from(([0, 0, 0, 0], LOCAL_CMD_PORT));
-        let cmd_queue = UdpSocket::bind(cmd_bind_addr).expect("Unable to create UDP command socket");
-        cmd_queue.connect(SocketAddr::from((TELLO_IP, TELLO_CMD_PORT))).expect("Failed to connect to Tello command");
+    pub fn connect(config: TelloConfig) -> Result<Tello, TelloError> {
+        let cmd_queue = UdpSocket::bind(config.local_cmd_addr())?;
+        cmd_queue.connect(config.drone_cmd_addr())?;
 
-        let cmd_socket_read = cmd_queue.try_clone().expect("Failed to clone socket");
-        cmd_socket_read.set_read_timeout(Some(Duration::from_secs(1))).expect("Failed to set cmd read timeout");
+        let cmd_socket_read = cmd_queue.try_clone()?;
+        cmd_socket_read.set_read_timeout(Some(config.read_timeout))?;
 
         let state = Arc::new(Mutex::new(State::new()));
         let is_running = Arc::new(AtomicBool::new(true));
@@ -327,86 +452,443 @@ impl Tello {
         let connect_condition = Arc::new((Mutex::new(false), Condvar::new()));
         let connect_condition_signaller = connect_condition.clone();
 
+        let seq_nr = Arc::new(AtomicU16::new(0));
+
         let is_running_cmd_listen = is_running.clone();
         let state_cmd_listen = state.clone();
-        let cmd_listen_thread = Some(thread::spawn(move || {
-            Self::handle_tello_msg(is_running_cmd_listen, cmd_socket_read, state_cmd_listen, connect_condition_signaller)
+        let seq_nr_cmd_listen = seq_nr.clone();
+        let mut cmd_listen_thread = Some(thread::spawn(move || {
+            Self::handle_tello_msg(is_running_cmd_listen,
+                                   cmd_socket_read,
+                                   state_cmd_listen,
+                                   seq_nr_cmd_listen,
+                                   connect_condition_signaller)
         }));
 
-        let connect_request = TelloConnectRequest::connect(video_port);
-        cmd_queue.send(connect_request.as_bytes().as_slice()).expect("Failed to send command to Tello");
+        let connect_request = TelloConnectRequest::connect(config.video_port);
+        if let Err(e) = cmd_queue.send(connect_request.as_bytes().as_slice()) {
+            is_running.store(false, Ordering::Relaxed);
+            join_thread!(cmd_listen_thread);
+            return Err(TelloError::Network(e));
+        }
 
         {
             let (lock, cvar) = &*connect_condition;
             let result = cvar.wait_timeout_while(
                 lock.lock().unwrap(),
-                Duration::from_secs(10),
+                config.connect_timeout,
                 |&mut connected| !connected,
             ).unwrap();
             if result.1.timed_out() {
-                return Err("Timed out connecting to Tello");
+                is_running.store(false, Ordering::Relaxed);
+                join_thread!(cmd_listen_thread);
+                return Err(TelloError::ConnectTimeout);
             }
         }
 
+        let watchdog_socket = cmd_queue.try_clone()?;
+        let is_running_watchdog = is_running.clone();
+        let state_watchdog = state.clone();
+        let config_watchdog = config.clone();
+        let connection_watchdog_thread = Some(thread::spawn(move || {
+            Self::watch_connection(is_running_watchdog,
+                                   watchdog_socket,
+                                   state_watchdog,
+                                   config_watchdog)
+        }));
+
         Ok(Tello {
+            config,
             is_running,
 
             cmd_listen_thread,
+            connection_watchdog_thread,
             cmd_queue,
             state,
-            seq_nr: Arc::new(AtomicU16::new(0)),
+            seq_nr,
+            picture_lock: Mutex::new(()),
 
-            video_raw_receive_thread: None,
-            video_frame_thread: None,
+            video_stream: None,
             video_ping_thread: None
         })
     }
 
-    fn takeoff(&self) {
-        self.send_raw(&TelloGram::from(
-            Commands::Takeoff,
-            self.seq_nr.fetch_add(1, Ordering::SeqCst)
-        ));
+    pub fn set_telemetry_listener(&self, listener: Sender<Telemetry>) {
+        self.state.lock().unwrap().telemetry_listener = Some(listener);
     }
 
-    fn land(&self) {
-        self.send_raw(&TelloGram::from(
-            Commands::Land,
-            self.seq_nr.fetch_add(1, Ordering::SeqCst)
-        ));
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().is_connected
     }
 
-    fn flip(&self, direction: FlipDirection) {
-        self.send_raw(&TelloGram::from(
-            Commands::Flip(direction),
-            self.seq_nr.fetch_add(1, Ordering::SeqCst)
-        ));
+    // Lands the drone if it is airborne, then stops all worker threads. Every worker
+    // wakes up at least once per read timeout, so this returns within a bounded time.
+    // Calling it more than once is harmless, and dropping the Tello calls it as well.
+    pub fn shutdown(&mut self) -> Result<(), TelloError> {
+        if !self.is_running.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        // Wait for the landing to be acknowledged while the command listener is still running
+        let is_flying = self.state.lock().unwrap().is_flying;
+        let land_result = if is_flying {
+            self.land().and_then(|landing| landing.wait())
+        } else {
+            Ok(())
+        };
+
+        self.is_running.store(false, Ordering::Relaxed);
+
+        // Dropping the stream stops the pipeline and joins its threads
+        self.video_stream = None;
+        join_thread!(self.video_ping_thread);
+
+        join_thread!(self.connection_watchdog_thread);
+        join_thread!(self.cmd_listen_thread);
+
+        land_result
+    }
+
+    pub fn takeoff(&self) -> Result<CommandHandle, TelloError> {
+        self.send_acknowledged(Commands::Takeoff)
+    }
+
+    pub fn land(&self) -> Result<CommandHandle, TelloError> {
+        self.send_acknowledged(Commands::Land)
+    }
+
+    // Takes off once the drone has been thrown into the air
+    pub fn throw_takeoff(&self) -> Result<CommandHandle, TelloError> {
+        self.send_acknowledged(Commands::ThrowTakeoff)
+    }
+
+    // Descends until a hand below the drone is detected and then stops the motors
+    pub fn palm_land(&self) -> Result<CommandHandle, TelloError> {
+        self.send_acknowledged(Commands::PalmLand)
+    }
+
+    pub fn flip(&self, direction: FlipDirection) -> Result<CommandHandle, TelloError> {
+        self.send_acknowledged(Commands::Flip(direction))
+    }
+
+    // The binary protocol has no known message to stop the motors, but the drone accepts
+    // text SDK commands on the same port. Entering SDK mode with "command" lets the SDK
+    // "emergency" command through, both are answered with a plain "ok". This skips the
+    // acknowledged command machinery and keeps sending until the drone answers or the
+    // Tello is shut down, since giving up is never the right call here.
+    pub fn emergency(&self) -> Result<EmergencyHandle, TelloError> {
+        let cmd_socket = self.cmd_queue.try_clone()?;
+        cmd_socket.send(b"command")?;
+
+        let (ack_sender, ack_receiver) = channel();
+        self.state.lock().unwrap().text_acks.push(ack_sender);
+
+        let (result_sender, result_receiver) = channel();
+        let is_running = self.is_running.clone();
+        let ack_timeout = self.config.ack_timeout;
+        thread::spawn(move || {
+            let mut result = Err(TelloError::NoEmergencyAck);
+            let mut in_sdk_mode = false;
+            while is_running.load(Ordering::Relaxed) {
+                if ack_receiver.recv_timeout(ack_timeout).is_ok() {
+                    if in_sdk_mode {
+                        result = Ok(());
+                        break;
+                    }
+                    in_sdk_mode = true;
+                    // Late answers to a repeated "command" must not pass for the emergency ack
+                    while ack_receiver.try_recv().is_ok() {}
+                }
+
+                let request: &[u8] = if in_sdk_mode { b"emergency" } else { b"command" };
+                if let Err(e) = cmd_socket.send(request) {
+                    result = Err(TelloError::Network(e));
+                    break;
+                }
+            }
+
+            // The caller may have dropped the handle without waiting for the result
+            let _ = result_sender.send(result);
+        });
+
+        Ok(EmergencyHandle { result: result_receiver })
+    }
+
+    // Sends the command and re-sends it with the same sequence number until the command
+    // listener sees the matching ack, or the retries run out
+    fn send_acknowledged(&self, command: Commands) -> Result<CommandHandle, TelloError> {
+        let id = command.id();
+        let sequence = self.seq_nr.fetch_add(1, Ordering::SeqCst);
+        let packet = TelloGram::from(command, sequence);
+        let cmd_socket = self.cmd_queue.try_clone()?;
+
+        let (ack_sender, ack_receiver) = channel();
+        self.state.lock().unwrap().pending_acks.insert((id, sequence), ack_sender);
+        if let Err(e) = self.send_raw(&packet) {
+            self.state.lock().unwrap().pending_acks.remove(&(id, sequence));
+            return Err(e);
+        }
+
+        let (result_sender, result_receiver) = channel();
+        let is_running = self.is_running.clone();
+        let state = self.state.clone();
+        let ack_timeout = self.config.ack_timeout;
+        let ack_retries = self.config.ack_retries;
+        thread::spawn(move || {
+            let mut result = Err(TelloError::NoAck { id, sequence });
+            for attempt in 0..=ack_retries {
+                if attempt > 0 {
+                    if !is_running.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if let Err(e) = cmd_socket.send(&packet) {
+                        result = Err(TelloError::Network(e));
+                        break;
+                    }
+                }
+
+                if let Ok(payload) = ack_receiver.recv_timeout(ack_timeout) {
+                    result = Ok(payload);
+                    break;
+                }
+            }
+
+            state.lock().unwrap().pending_acks.remove(&(id, sequence));
+            // The caller may have dropped the handle without waiting for the result
+            let _ = result_sender.send(result);
+        });
+
+        Ok(CommandHandle { id, sequence, result: result_receiver })
+    }
+
+    // Altitude limit in meters
+    pub fn set_max_height(&self, meters: u16) -> Result<(), TelloError> {
+        self.apply_setting(Commands::SetMaxHeight(meters))?;
+        self.state.lock().unwrap().max_height = Some(meters);
+        Ok(())
+    }
+
+    pub fn get_max_height(&self) -> Result<u16, TelloError> {
+        self.query(Commands::GetMaxHeight, |state| state.max_height)
+    }
+
+    // Battery percentage at which the drone warns about a low battery
+    pub fn set_low_battery_threshold(&self, percentage: u8) -> Result<(), TelloError> {
+        self.apply_setting(Commands::SetLowBatteryThreshold(percentage))?;
+        self.state.lock().unwrap().low_battery_threshold = Some(percentage);
+        Ok(())
+    }
+
+    pub fn get_low_battery_threshold(&self) -> Result<u8, TelloError> {
+        self.query(Commands::GetLowBatteryThreshold, |state| state.low_battery_threshold)
+    }
+
+    // Maximum tilt in degrees, which limits how hard the drone accelerates
+    pub fn set_attitude_angle(&self, degrees: f32) -> Result<(), TelloError> {
+        self.apply_setting(Commands::SetAttitudeAngle(degrees))?;
+        self.state.lock().unwrap().attitude_angle = Some(degrees);
+        Ok(())
+    }
+
+    pub fn get_attitude_angle(&self) -> Result<f32, TelloError> {
+        self.query(Commands::GetAttitudeAngle, |state| state.attitude_angle)
+    }
+
+    pub fn get_version(&self) -> Result<String, TelloError> {
+        self.query(Commands::GetVersion, |state| state.firmware_version.clone())
+    }
+
+    pub fn get_loader_version(&self) -> Result<String, TelloError> {
+        self.query(Commands::GetLoaderVersion, |state| state.loader_version.clone())
+    }
+
+    pub fn set_video_bitrate(&self, bitrate: VideoBitrate) -> Result<(), TelloError> {
+        self.apply_setting(Commands::SetVideoBitrate(bitrate))
+    }
+
+    // The decoder only picks up the new resolution with the next SPS, so it is requested right
+    // away instead of waiting for the periodic request. Frames are sized after the decoded
+    // stream, so the player adapts on its own.
+    pub fn set_video_mode(&self, mode: VideoMode) -> Result<(), TelloError> {
+        self.apply_setting(Commands::SetVideoMode(mode))?;
+        self.state.lock().unwrap().video_mode = mode;
+        self.send_raw(&TelloGram::from(Commands::VideoSPSPPS, self.seq_nr.fetch_add(1, Ordering::SeqCst)))
+    }
+
+    pub fn video_mode(&self) -> VideoMode {
+        self.state.lock().unwrap().video_mode
+    }
+
+    pub fn set_exposure(&self, exposure: Exposure) -> Result<(), TelloError> {
+        self.apply_setting(Commands::SetExposure(exposure))
+    }
+
+    // Latest strength reported by the drone, in percent
+    pub fn wifi_strength(&self) -> Option<WifiStrength> {
+        self.state.lock().unwrap().wifi_strength
+    }
+
+    fn apply_setting(&self, command: Commands) -> Result<(), TelloError> {
+        let id = command.id();
+        let payload = self.send_acknowledged(command)?.response()?;
+        check_result_code(id, &payload)
+    }
+
+    // The command listener decodes the response into the state before resolving the query
+    fn query<T, F: Fn(&State) -> Option<T>>(&self, command: Commands, value: F) -> Result<T, TelloError> {
+        let id = command.id();
+        let payload = self.send_acknowledged(command)?.response()?;
+        check_result_code(id, &payload)?;
+        value(&self.state.lock().unwrap()).ok_or(TelloError::InvalidResponse { id, payload })
+    }
+
+    // Takes a picture and downloads the JPEG, which takes a few seconds. Chunks may arrive
+    // out of order or not at all, so whenever the download stalls the last ack is repeated
+    // to make the drone send the missing piece again.
+    pub fn take_picture(&self) -> Result<Vec<u8>, TelloError> {
+        let _picture = self.picture_lock.lock().unwrap();
+
+        let (sender, receiver) = channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.file_transfer = None;
+            state.picture_listener = Some(sender);
+        }
+
+        let result = self.download_picture(receiver);
+        self.state.lock().unwrap().picture_listener = None;
+        result
+    }
+
+    fn download_picture(&self, picture: Receiver<Vec<u8>>) -> Result<Vec<u8>, TelloError> {
+        self.send_acknowledged(Commands::TakePicture)?.wait()?;
+
+        let mut progress = None;
+        let mut last_progress = Instant::now();
+        while self.is_running.load(Ordering::Relaxed) {
+            if let Ok(picture) = picture.recv_timeout(self.config.ack_timeout) {
+                return Ok(picture);
+            }
+
+            let request = {
+                let state = self.state.lock().unwrap();
+                let transfer = state.file_transfer.as_ref();
+                let received = transfer.map(|transfer| transfer.received_chunks());
+                if received != progress {
+                    progress = received;
+                    last_progress = Instant::now();
+                    continue;
+                }
+                if last_progress.elapsed() > self.config.file_transfer_timeout {
+                    return Err(TelloError::FileTransferStalled);
+                }
+
+                // Acking the file size asks for the first piece, acking a piece asks for the next one
+                match transfer.and_then(|transfer| Some((transfer.info().file_id, transfer.first_incomplete_piece()?))) {
+                    Some((_, 0)) => Commands::FileSizeAck,
+                    Some((file_id, piece)) => Commands::FilePieceAck { file_id, piece: piece - 1, is_last: false },
+                    // Nothing to ask for until the drone announces the file
+                    None => continue
+                }
+            };
+            self.send_raw(&TelloGram::from(request, self.seq_nr.fetch_add(1, Ordering::SeqCst)))?;
+        }
+
+        Err(TelloError::FileTransferStalled)
     }
 
-    fn set_joystick(&self, controller: controller::State) {
+    pub fn speed_mode(&self) -> SpeedMode {
+        self.state.lock().unwrap().speed_mode
+    }
+
+    // Applies to every stick packet sent from now on
+    pub fn set_speed_mode(&self, speed_mode: SpeedMode) {
+        let mut state = self.state.lock().unwrap();
+        if state.speed_mode != speed_mode {
+            state.speed_mode = speed_mode;
+            state.publish(Telemetry::SpeedMode(speed_mode));
+        }
+    }
+
+    pub fn toggle_speed_mode(&self) -> SpeedMode {
+        let speed_mode = match self.speed_mode() {
+            SpeedMode::Slow => SpeedMode::Fast,
+            SpeedMode::Fast => SpeedMode::Slow,
+        };
+        self.set_speed_mode(speed_mode);
+        speed_mode
+    }
+
+    pub fn set_joystick(&self, controller: controller::State) -> Result<(), TelloError> {
         self.send_raw(&TelloGram::from(
             Commands::Joystick {
                 lx: controller.joystick_left_x,
                 ly: controller.joystick_left_y,
                 rx: controller.joystick_right_x,
-                ry: controller.joystick_right_y
+                ry: controller.joystick_right_y,
+                speed_mode: self.speed_mode()
             },
             0 // unused
-        ));
+        ))
+    }
+
+    pub fn send_raw(&self, data: &[u8]) -> Result<(), TelloError> {
+        TelloGram::parse(data).map_err(TelloError::Protocol)?;
+
+        self.cmd_queue.send(data)?;
+        Ok(())
     }
 
-    fn send_raw(&self, data: &[u8]) {
-        let gram = unsafe { &*data.as_ptr().cast::<TelloGram>() };
-        if !gram.is_valid() {
-            println!("Sending invalid TelloGram {:?}", &data);
+    // The drone only starts streaming log records once its log header has been
+    // acknowledged with the log id found in the first two bytes of the header payload
+    fn log_header_ack(header: &TelloGram, seq: u16) -> Option<Vec<u8>> {
+        let payload = header.payload();
+        if payload.len() < 2 {
+            return None;
         }
 
-        self.cmd_queue.send(data).unwrap();
+        let log_id = u16::from_le_bytes([payload[0], payload[1]]);
+        Some(TelloGram::from(Commands::LogHeaderAck(log_id), seq))
+    }
+
+    // Acks a piece once the chunk completing it arrives and hands the file to the picture
+    // listener after the last one. Repeated chunks are not acked again, a lost ack is made
+    // up for by the re-request when the download stalls.
+    fn receive_file_chunk(cmd_socket: &UdpSocket, state: &Mutex<State>, seq_nr: &AtomicU16, chunk: FileChunk) {
+        let mut state = state.lock().unwrap();
+        let state = &mut *state;
+        let piece = chunk.chunk / CHUNKS_PER_PIECE;
+        let transfer = match &mut state.file_transfer {
+            Some(transfer) => transfer,
+            None => return
+        };
+        if !transfer.add(chunk) || !transfer.is_piece_complete(piece) {
+            return;
+        }
+
+        let info = *transfer.info();
+        let mut replies = vec![
+            Commands::FilePieceAck { file_id: info.file_id, piece, is_last: piece + 1 == info.piece_count() }
+        ];
+        if transfer.is_complete() {
+            replies.push(Commands::FileDone { file_id: info.file_id, size: info.size });
+            let file = transfer.to_bytes();
+            if let Some(listener) = state.picture_listener.take() {
+                let _ = listener.send(file);
+            }
+        }
+
+        for reply in replies {
+            if let Err(e) = cmd_socket.send(&TelloGram::from(reply, seq_nr.fetch_add(1, Ordering::SeqCst))) {
+                println!("Failed to acknowledge file piece: {}", e);
+            }
+        }
     }
 
     fn handle_tello_msg(is_running: Arc<AtomicBool>,
                         cmd_socket_read: UdpSocket,
                         state: Arc<Mutex<State>>,
+                        seq_nr: Arc<AtomicU16>,
                         connect_condition: Arc<(Mutex<bool>, Condvar)>) {
         let mut buffer: [u8; 4096] = [0; 4096];
             
@@ -414,193 +896,879 @@ impl Tello {
             match cmd_socket_read.recv(&mut buffer) {
                 Ok(num_bytes) => {
                     // println!("Command package of {} bytes: {:?}", num_bytes, &buffer[..num_bytes]);
-
-                    if buffer.starts_with("conn_ack:".as_bytes()) {
-                        state.lock().unwrap().is_connected = true;
+                    state.lock().unwrap().last_seen = Some(Instant::now());
+
+                    if buffer[..num_bytes].starts_with("conn_ack:".as_bytes()) {
+                        {
+                            let mut state = state.lock().unwrap();
+                            state.is_connected = true;
+                            if let Some(disconnected_at) = state.disconnected_at.take() {
+                                println!("Reconnected to Tello after {:?}", disconnected_at.elapsed());
+                                state.publish(Telemetry::Reconnected);
+                            }
+                        }
 
                         // Signal connection to initializer
                         let (lock, cvar) = &*connect_condition;
                         *(lock.lock().unwrap()) = true;
                         cvar.notify_one();
+                    } else if buffer[..num_bytes].starts_with(b"ok") {
+                        // Text SDK answer, only expected while an emergency stop is in flight
+                        state.lock().unwrap().text_acks.retain(|ack| ack.send(()).is_ok());
                     } else {
                         // Interpret as TelloGram
-                        let gram = unsafe { &*buffer.as_ptr().cast::<TelloGram>() };
-
-                        if !gram.is_valid() {
-                            println!("Received invalid TelloGram {:?}", &buffer[..num_bytes]);
-                            continue
-                        }
+                        let gram = match TelloGram::parse(&buffer[..num_bytes]) {
+                            Ok(gram) => gram,
+                            Err(e) => {
+                                state.lock().unwrap().report_invalid(e);
+                                continue
+                            }
+                        };
 
                         match gram.id() {
                             0x2 => {
                                 print!("0x2 connected received !!!!!!!!");  
                             },
+                            0x20 | 0x30 | 0x31 | 0x34 | 0x54 | 0x55 | 0x58 | 0x5c | 0x5d | 0x5e | 0x1055 | 0x1058 => {
+                                // Command acks, resolved through pending_acks below
+                            },
+                            0x1a => {
+                                match gram.payload() {
+                                    &[strength, interference, ..] => {
+                                        let wifi_strength = WifiStrength { strength, interference };
+                                        let mut state = state.lock().unwrap();
+                                        state.wifi_strength = Some(wifi_strength);
+                                        state.publish(Telemetry::Wifi(wifi_strength));
+                                    },
+                                    _ => state.lock().unwrap().report_invalid(truncated(&gram))
+                                }
+                            },
+                            0x35 => {
+                                if let Some(&light_strength) = gram.payload().first() {
+                                    state.lock().unwrap().light_strength = Some(light_strength);
+                                }
+                            },
+                            0x45 => {
+                                let mut state = state.lock().unwrap();
+                                state.firmware_version = decode_version(gram.payload());
+                                if let Some(version) = state.firmware_version.clone() {
+                                    state.publish(Telemetry::FirmwareVersion(version));
+                                }
+                            },
+                            0x49 => {
+                                let mut state = state.lock().unwrap();
+                                state.loader_version = decode_version(gram.payload());
+                                if let Some(version) = state.loader_version.clone() {
+                                    state.publish(Telemetry::LoaderVersion(version));
+                                }
+                            },
+                            0x62 => {
+                                match FileInfo::parse(gram.payload()) {
+                                    Some(info) => {
+                                        // The announcement is repeated until acked, which must not restart the download
+                                        let mut state = state.lock().unwrap();
+                                        if state.file_transfer.as_ref().map(|transfer| transfer.info().file_id) != Some(info.file_id) {
+                                            state.file_transfer = Some(FileAssembler::new(info));
+                                        }
+
+                                        let ack = TelloGram::from(Commands::FileSizeAck, seq_nr.fetch_add(1, Ordering::SeqCst));
+                                        if let Err(e) = cmd_socket_read.send(&ack) {
+                                            println!("Failed to acknowledge file size: {}", e);
+                                        }
+                                    },
+                                    None => state.lock().unwrap().report_invalid(truncated(&gram))
+                                }
+                            },
+                            0x63 => {
+                                match FileChunk::parse(gram.payload()) {
+                                    Some(chunk) => Self::receive_file_chunk(&cmd_socket_read, &state, &seq_nr, chunk),
+                                    None => state.lock().unwrap().report_invalid(truncated(&gram))
+                                }
+                            },
                             0x56 => {
-                                let data = FlightData::from(&gram.payload());
-                                println!("{:?}", data);
+                                match FlightData::parse(gram.payload()) {
+                                    Some(data) => {
+                                        let mut state = state.lock().unwrap();
+                                        state.is_flying = data.is_flying();
+                                        state.flight_data = Some(data);
+                                        state.publish(Telemetry::FlightData(data));
+                                    },
+                                    None => state.lock().unwrap().report_invalid(truncated(&gram))
+                                }
+                            },
+                            0x1050 => {
+                                match Self::log_header_ack(&gram, seq_nr.fetch_add(1, Ordering::SeqCst)) {
+                                    Some(ack) => {
+                                        if let Err(e) = cmd_socket_read.send(&ack) {
+                                            println!("Failed to acknowledge log header: {}", e);
+                                        }
+                                    },
+                                    None => state.lock().unwrap().report_invalid(truncated(&gram))
+                                }
+                            },
+                            0x1051 => {
+                                let mut state = state.lock().unwrap();
+                                for record in log_data::parse(gram.payload()) {
+                                    state.publish(Telemetry::from_log_record(record));
+                                }
+                            },
+                            0x1056 => {
+                                state.lock().unwrap().max_height = match response_value(gram.payload()) {
+                                    Some(&[low, high, ..]) => Some(u16::from_le_bytes([low, high])),
+                                    _ => None
+                                };
+                            },
+                            0x1057 => {
+                                state.lock().unwrap().low_battery_threshold = match response_value(gram.payload()) {
+                                    Some(&[threshold, ..]) => Some(threshold),
+                                    _ => None
+                                };
+                            },
+                            0x1059 => {
+                                state.lock().unwrap().attitude_angle = match response_value(gram.payload()) {
+                                    Some(&[b0, b1, b2, b3, ..]) => Some(f32::from_le_bytes([b0, b1, b2, b3])),
+                                    _ => None
+                                };
                             },
                             _ => {
                                 println!("Unhandled package type {}", gram.id());
                             }
                         }
 
+                        // Resolved last, so responses have been decoded into the state by the time the caller wakes up
+                        if let Some(ack) = state.lock().unwrap().pending_acks.remove(&(gram.id(), gram.sequence())) {
+                            let _ = ack.send(gram.payload().to_vec());
+                        }
+
                         /*
-                        println!("Header: {:?}", gram.header());
                         println!("Size: {:?}", gram.size());
-                        println!("CRC8: {:?}", gram.crc8());
                         println!("Packet direction: {:?}", gram.packet_direction());
                         println!("Type: {:?}", gram.packet_type());
                         println!("Subtype: {:?}", gram.packet_subtype());
                         println!("Id: {:?}", gram.id());
                         println!("Sequence: {:?}", gram.sequence());
-                        println!("CRC16: {:?}", gram.crc16());
                         println!("Payload: {:?}", gram.payload());
                         println!("");
                         */
                     }
                 },
-                Err(e) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => (),
+                // Reported when the drone is unreachable, which the connection watchdog deals with
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => (),
+                Err(e) => println!("Failed to receive command package: {}", e),
             }
         }
     }
 
-    fn start_video(&mut self, frame_channel: Sender<player::Frame>) {
-        let (appsource, appsink) = Self::initialize_video_pipeline();
-
-        let video_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], VIDEO_PORT))).expect("Failed to create video socket");
+    // Marks the connection as lost once the drone has been silent for longer than the
+    // heartbeat timeout, and keeps re-sending the connect request with exponential backoff
+    // until the command listener sees a new conn_ack
+    fn watch_connection(is_running: Arc<AtomicBool>,
+                        cmd_socket: UdpSocket,
+                        state: Arc<Mutex<State>>,
+                        config: TelloConfig) {
+        let connect_request = TelloConnectRequest::connect(config.video_port);
+        let mut backoff = config.reconnect_backoff;
+        let mut next_attempt = Instant::now();
 
-        let tello_video_listen_thread_running = self.is_running.clone();
-        self.video_raw_receive_thread = Some(thread::spawn(move || {
-            let mut buffer = [0; 4096];            
-            while (*tello_video_listen_thread_running).load(Ordering::Relaxed) {
-                match video_socket.recv(&mut buffer) {
-                    Ok(num_bytes) => {
-                        let mut databuf = vec![0; num_bytes - 2];
-                        for i in 0..num_bytes - 2 {
-                            databuf[i] = buffer[i + 2];
-                        }
+        while (*is_running).load(Ordering::Relaxed) {
+            thread::sleep(WATCHDOG_INTERVAL);
 
-                        appsource.push_buffer(gst::buffer::Buffer::from_slice(databuf)).expect("Failed to push vidoe buffer");
-                    },
-                    Err(e) => println!("Failed to receive video buffer: {}", e)
+            let mut state = state.lock().unwrap();
+            if state.is_connected {
+                let is_silent = state.last_seen.map_or(true, |last_seen| last_seen.elapsed() > config.heartbeat_timeout);
+                if !is_silent {
+                    continue;
                 }
+
+                println!("Lost connection to Tello, reconnecting");
+                state.is_connected = false;
+                state.disconnected_at = Some(Instant::now());
+                state.publish(Telemetry::Disconnected);
+
+                backoff = config.reconnect_backoff;
+                next_attempt = Instant::now();
             }
-        }));
 
-        let video_processor_thread_running = self.is_running.clone();
-        self.video_frame_thread = Some(thread::spawn(move || {
-            while (*video_processor_thread_running).load(Ordering::Relaxed) {
-                match appsink.try_pull_sample(gst::ClockTime::from_seconds(1)) {
-                    Some(sample) => {
-                        // TODO: Get width, height from Sample::caps
-
-                        let buffer = sample.get_buffer().unwrap();
-                        let mut data = vec![0; buffer.get_size()];
-                        buffer.copy_to_slice(0, &mut data).unwrap();
-
-                        frame_channel.send(player::Frame {
-                            width: 960,
-                            height: 720,
-                            data: data
-                        }).expect("Failed to send frame");
-                    },
-                    None => ()
+            if Instant::now() >= next_attempt {
+                if let Err(e) = cmd_socket.send(connect_request.as_bytes().as_slice()) {
+                    println!("Failed to send reconnect request: {}", e);
                 }
+                next_attempt = Instant::now() + backoff;
+                backoff = cmp::min(backoff * 2, MAX_RECONNECT_BACKOFF);
             }
-        }));
+        }
+    }
+
+    pub fn start_video(&mut self, frame_channel: Sender<player::Frame>) -> Result<(), TelloError> {
+        let video_socket = UdpSocket::bind(self.config.local_video_addr())?;
+        let tello_cmd = self.cmd_queue.try_clone()?;
+        self.video_stream = Some(VideoStream::start(video_socket, 2, self.config.read_timeout, frame_channel)?);
 
         let video_ping_thread_running = self.is_running.clone();
-        let tello_cmd = self.cmd_queue.try_clone().unwrap();
+        let video_ping_state = self.state.clone();
         let sequence_number = self.seq_nr.clone();
         self.video_ping_thread = Some(thread::spawn(move || {
             while (*video_ping_thread_running).load(Ordering::Relaxed) {
-                tello_cmd.send(&TelloGram::from(
+                // Pause the keepalive while the watchdog reconnects, it resumes on the next conn_ack
+                if !video_ping_state.lock().unwrap().is_connected {
+                    thread::sleep(WATCHDOG_INTERVAL);
+                    continue;
+                }
+
+                let request = TelloGram::from(
                     Commands::VideoSPSPPS,
                     sequence_number.fetch_add(1, Ordering::SeqCst)
-                )).expect("Failed to send video request");
-                thread::sleep(time::Duration::from_millis(1000));
+                );
+                if let Err(e) = tello_cmd.send(&request) {
+                    println!("Failed to send video request: {}", e);
+                }
+                sleep_while_running(&video_ping_thread_running, Duration::from_millis(1000));
             }
         }));
+
+        Ok(())
+    }
+
+    // Recording stops on its own when the Tello shuts down, the file is finalized either way
+    pub fn start_recording<P: AsRef<Path>>(&self, path: P) -> Result<(), TelloError> {
+        Ok(self.video_stream()?.start_recording(path.as_ref())?)
+    }
+
+    pub fn stop_recording(&self) -> Result<(), TelloError> {
+        Ok(self.video_stream()?.stop_recording()?)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        match &self.video_stream {
+            Some(video_stream) => video_stream.is_recording(),
+            None => false
+        }
     }
 
-    fn initialize_video_pipeline() -> (gst_app::AppSrc, gst_app::AppSink) {
-        gst::init().expect("Failed to init gstreamer");
+    // Captures the raw video packets, which VideoStream::replay can decode again without the drone
+    pub fn start_capture<P: AsRef<Path>>(&self, path: P) -> Result<(), TelloError> {
+        Ok(self.video_stream()?.start_capture(path.as_ref())?)
+    }
 
-        let pipeline = gst::Pipeline::new(None);
-        let source = gst::ElementFactory::make("appsrc", None).expect("Failed to create appsource");
-        let h264parse = gst::ElementFactory::make("h264parse", None).expect("Failed to create h264parse");
-        let avdec_h264 = gst::ElementFactory::make("avdec_h264", None).expect("Failed to create avdec_h264");
-        let videoconvert = gst::ElementFactory::make("videoconvert", None).expect("Failed to create videoconvert");
-        let sink = gst::ElementFactory::make("appsink", None).expect("Failed to create appsink");
+    pub fn stop_capture(&self) -> Result<(), TelloError> {
+        Ok(self.video_stream()?.stop_capture()?)
+    }
 
-        pipeline.add_many(&[&source, &h264parse, &avdec_h264, &videoconvert, &sink]).expect("Failed to create pipeline");
-        source.link(&h264parse).expect("Failed to link");
-        h264parse.link(&avdec_h264).expect("Failed to link");
-        avdec_h264.link(&videoconvert).expect("Failed to link");
-        videoconvert.link(&sink).expect("Failed to link");
+    pub fn is_capturing(&self) -> bool {
+        match &self.video_stream {
+            Some(video_stream) => video_stream.is_capturing(),
+            None => false
+        }
+    }
 
-        let appsource = source.dynamic_cast::<gst_app::AppSrc>().expect("Pipeline should be an appsource!");
-        let appsink = sink.dynamic_cast::<gst_app::AppSink>().expect("Pipeline should be an appsink!");
+    fn video_stream(&self) -> Result<&VideoStream, TelloError> {
+        self.video_stream.as_ref().ok_or(TelloError::VideoNotStarted)
+    }
+}
 
-        appsource.set_latency(gst::ClockTime::from_mseconds(0), gst::ClockTime::from_mseconds(10));
-        appsource.set_property_is_live(true);
-        appsource.set_stream_type(gst_app::AppStreamType::Stream);
+impl DroneControl for Tello {
+    // Waits for the ack the inherent command methods leave to the caller
+    fn takeoff(&self) -> Result<(), DroneError> {
+        Ok(Tello::takeoff(self)?.wait()?)
+    }
 
-        appsink.set_caps(Some(&gst::Caps::new_simple(
-            "video/x-raw",
-            &[
-                ("format", &"RGBA")
-            ]
-        )));
+    fn land(&self) -> Result<(), DroneError> {
+        Ok(Tello::land(self)?.wait()?)
+    }
 
-        pipeline.set_state(gst::State::Playing).expect("Failed to change pipeline state to play");
+    fn emergency(&self) -> Result<(), DroneError> {
+        Ok(Tello::emergency(self)?.wait()?)
+    }
 
-        (appsource, appsink)
+    fn flip(&self, direction: FlipDirection) -> Result<(), DroneError> {
+        Ok(Tello::flip(self, direction)?.wait()?)
+    }
+
+    fn set_sticks(&self, sticks: controller::State) -> Result<(), DroneError> {
+        Ok(self.set_joystick(sticks)?)
+    }
+
+    fn set_telemetry_listener(&mut self, listener: Sender<Telemetry>) -> Result<(), DroneError> {
+        Tello::set_telemetry_listener(self, listener);
+        Ok(())
+    }
+
+    fn start_video(&mut self, frame_channel: Sender<player::Frame>) -> Result<(), DroneError> {
+        Ok(Tello::start_video(self, frame_channel)?)
     }
 }
 
-fn main() {
-    let is_running = Arc::new(AtomicBool::new(true));
+#[test]
+fn test_log_header_ack() {
+    // Log id 0x029d sits in packet bytes 9-10, where tellopy and TelloLib read it from
+    let header = [
+        0xcc, 0xb8, 0x00, 0x09, 0x90, 0x50, 0x10, 0x3f, 0x00, 0x9d, 0x02, 0x00,
+        0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x89, 0xad
+    ];
+    let expected_ack = [0xcc, 0x70, 0x00, 0xcb, 0x50, 0x50, 0x10, 0x05, 0x00, 0x00, 0x9d, 0x02, 0x07, 0x5e];
+
+    let gram = TelloGram::parse(&header).unwrap();
+    assert_eq!(gram.id(), 0x1050);
+    assert_eq!(Tello::log_header_ack(&gram, 5), Some(expected_ack.to_vec()));
+
+    let truncated = TelloGram::new(crate::protocol::TelloGramDirection::FromDrone, crate::protocol::PackageType::Data1, 0, 0x1050, 0, &[0x9d]);
+    assert_eq!(Tello::log_header_ack(&truncated, 5), None);
+}
 
-    let (controller_events_sender, controller_events_receiver) = channel();
+#[test]
+fn test_config_builder() {
+    let default = TelloConfig::new();
+    assert_eq!(default.drone_cmd_addr(), SocketAddr::from((TELLO_IP, TELLO_CMD_PORT)));
+    assert_eq!(default.local_cmd_addr(), SocketAddr::from(([0, 0, 0, 0], LOCAL_CMD_PORT)));
+    assert_eq!(default.local_video_addr(), SocketAddr::from(([0, 0, 0, 0], VIDEO_PORT)));
+
+    let config = TelloConfig::new()
+        .drone_ip([127, 0, 0, 1])
+        .remote_cmd_port(9889)
+        .local_cmd_port(0)
+        .video_port(9040)
+        .bind_ip([127, 0, 0, 1])
+        .connect_timeout(Duration::from_millis(500))
+        .read_timeout(Duration::from_millis(50))
+        .heartbeat_timeout(Duration::from_millis(300))
+        .reconnect_backoff(Duration::from_millis(20))
+        .ack_timeout(Duration::from_millis(100))
+        .ack_retries(5)
+        .file_transfer_timeout(Duration::from_secs(2));
+    assert_eq!(config.drone_cmd_addr(), SocketAddr::from(([127, 0, 0, 1], 9889)));
+    assert_eq!(config.local_cmd_addr(), SocketAddr::from(([127, 0, 0, 1], 0)));
+    assert_eq!(config.local_video_addr(), SocketAddr::from(([127, 0, 0, 1], 9040)));
+    assert_eq!(config.connect_timeout, Duration::from_millis(500));
+    assert_eq!(config.read_timeout, Duration::from_millis(50));
+    assert_eq!(config.heartbeat_timeout, Duration::from_millis(300));
+    assert_eq!(config.reconnect_backoff, Duration::from_millis(20));
+    assert_eq!(config.ack_timeout, Duration::from_millis(100));
+    assert_eq!(config.ack_retries, 5);
+    assert_eq!(config.file_transfer_timeout, Duration::from_secs(2));
+}
+
+#[test]
+fn test_connect_to_configured_address() {
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let config = TelloConfig::new()
+        .drone_ip([127, 0, 0, 1])
+        .remote_cmd_port(drone.local_addr().unwrap().port())
+        .local_cmd_port(0)
+        .video_port(9041)
+        .connect_timeout(Duration::from_millis(200))
+        .read_timeout(Duration::from_millis(20));
+
+    // The stand-in never answers, so connecting must give up after the configured timeout
+    let started = Instant::now();
+    match Tello::connect(config) {
+        Err(TelloError::ConnectTimeout) => (),
+        Err(e) => panic!("Expected connect timeout, got {}", e),
+        Ok(_) => panic!("Connected without an acknowledgement"),
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    let mut request = [0u8; 64];
+    let num_bytes = drone.recv(&mut request).unwrap();
+    assert_eq!(&request[..num_bytes], b"conn_req:\x51\x23");
+}
 
-    let mut controller = controller::Controller::get_controller(0).unwrap();
-    controller.set_event_listener(controller_events_sender);
-    let controller_state = controller.get_state();
-    let controller_is_running = is_running.clone();
-    let controller_thread = thread::spawn(move || {
-        controller.start(controller_is_running);
+#[cfg(test)]
+fn wait_for_telemetry(receiver: &Receiver<Telemetry>, matches: fn(&Telemetry) -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while Instant::now() < deadline {
+        if let Ok(telemetry) = receiver.recv_timeout(Duration::from_millis(50)) {
+            if matches(&telemetry) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[test]
+fn test_reconnect_after_drone_goes_silent() {
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .drone_ip([127, 0, 0, 1])
+        .remote_cmd_port(drone.local_addr().unwrap().port())
+        .local_cmd_port(0)
+        .video_port(9042)
+        .connect_timeout(Duration::from_secs(2))
+        .read_timeout(Duration::from_millis(20))
+        .heartbeat_timeout(Duration::from_millis(200))
+        .reconnect_backoff(Duration::from_millis(100));
+
+    let stand_in = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let (_, client) = drone.recv_from(&mut request).unwrap();
+        drone.send_to(b"conn_ack:\x52\x23", client).unwrap();
+        drone
     });
+    let tello = Tello::connect(config).unwrap();
+    let drone = stand_in.join().unwrap();
+    assert!(tello.is_connected());
+
+    let (sender, receiver) = channel();
+    tello.set_telemetry_listener(sender);
+
+    // The stand-in stays silent, so the watchdog must notice and start asking to reconnect
+    assert!(wait_for_telemetry(&receiver, |telemetry| matches!(telemetry, Telemetry::Disconnected)));
+    assert!(!tello.is_connected());
+
+    let mut request = [0u8; 64];
+    let (num_bytes, client) = drone.recv_from(&mut request).unwrap();
+    assert_eq!(&request[..num_bytes], b"conn_req:\x52\x23");
+    let first_attempt = Instant::now();
+    let (num_bytes, _) = drone.recv_from(&mut request).unwrap();
+    assert_eq!(&request[..num_bytes], b"conn_req:\x52\x23");
+    assert!(first_attempt.elapsed() >= Duration::from_millis(50));
+
+    drone.send_to(b"conn_ack:\x52\x23", client).unwrap();
+    assert!(wait_for_telemetry(&receiver, |telemetry| matches!(telemetry, Telemetry::Reconnected)));
+    assert!(tello.is_connected());
+}
 
-    let mut tello = Tello::connect(VIDEO_PORT).unwrap();
-
-    let (video_sender, video_receiver) = channel();
-    let player = player::Player::new(video_receiver);
-    tello.start_video(video_sender);
-
-    let tello_cmd_loop_running = is_running.clone();
-    let tello_cmd_loop = thread::spawn(move || {
-        while (*tello_cmd_loop_running).load(Ordering::Relaxed) {
-            if let Ok(event) = controller_events_receiver.recv_timeout(Duration::from_millis(15)) {
-                match event {
-                    controller::Event::XPress => tello.takeoff(),
-                    controller::Event::CirclePress => tello.land(),
-                    controller::Event::LeftHat => tello.flip(FlipDirection::Left),
-                    controller::Event::UpHat => tello.flip(FlipDirection::Forward),
-                    controller::Event::RightHat => tello.flip(FlipDirection::Right),
-                    controller::Event::DownHat => tello.flip(FlipDirection::Backward),
-                    _ => ()
-                }
-            } else {
-                tello.set_joystick(*controller_state.lock().unwrap());
+#[test]
+fn test_shutdown_lands_and_returns_promptly() {
+    use crate::protocol::{ TelloGramDirection, PackageType };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .drone_ip([127, 0, 0, 1])
+        .remote_cmd_port(drone.local_addr().unwrap().port())
+        .local_cmd_port(0)
+        .video_port(9043)
+        .connect_timeout(Duration::from_secs(2))
+        .read_timeout(Duration::from_millis(50));
+
+    let stand_in = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let (_, client) = drone.recv_from(&mut request).unwrap();
+        drone.send_to(b"conn_ack:\x53\x23", client).unwrap();
+
+        // Report that the drone is in the air
+        let mut flight_data = [0u8; FlightData::PAYLOAD_SIZE];
+        flight_data[17] = 0x01;
+        let gram = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Get, 0, 0x56, 0, &flight_data);
+        drone.send_to(&gram.to_bytes(), client).unwrap();
+        drone
+    });
+    let mut tello = Tello::connect(config).unwrap();
+    let drone = stand_in.join().unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !tello.state.lock().unwrap().is_flying && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let stand_in = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let (num_bytes, client) = drone.recv_from(&mut request).unwrap();
+        let land = TelloGram::parse(&request[..num_bytes]).unwrap();
+        assert_eq!(land.id(), 0x55);
+
+        let ack = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Set, 0, land.id(), land.sequence(), &[0]);
+        drone.send_to(&ack.to_bytes(), client).unwrap();
+        drone
+    });
+
+    let started = Instant::now();
+    tello.shutdown().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(!tello.is_running.load(Ordering::Relaxed));
+    let drone = stand_in.join().unwrap();
+    let mut request = [0u8; 64];
+
+    // Dropping after an explicit shutdown must not block or land again
+    let started = Instant::now();
+    drop(tello);
+    assert!(started.elapsed() < Duration::from_millis(100));
+    drone.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    assert!(drone.recv(&mut request).is_err());
+}
+
+#[cfg(test)]
+pub(crate) fn connect_to_stand_in(drone: &UdpSocket, config: TelloConfig) -> (Tello, SocketAddr) {
+    let config = config
+        .drone_ip([127, 0, 0, 1])
+        .remote_cmd_port(drone.local_addr().unwrap().port())
+        .local_cmd_port(0)
+        .connect_timeout(Duration::from_secs(2))
+        .read_timeout(Duration::from_millis(20));
+
+    let stand_in = drone.try_clone().unwrap();
+    let handshake = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let (num_bytes, client) = stand_in.recv_from(&mut request).unwrap();
+        let mut ack = b"conn_ack:".to_vec();
+        ack.extend_from_slice(&request[9..num_bytes]);
+        stand_in.send_to(&ack, client).unwrap();
+        client
+    });
+    let tello = Tello::connect(config).unwrap();
+    (tello, handshake.join().unwrap())
+}
+
+#[test]
+fn test_command_resolves_on_matching_ack() {
+    use crate::protocol::{ TelloGramDirection, PackageType };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .video_port(9044)
+        .ack_timeout(Duration::from_millis(100))
+        .ack_retries(3);
+    let (tello, client) = connect_to_stand_in(&drone, config);
+
+    let takeoff = tello.takeoff().unwrap();
+    assert_eq!(takeoff.id(), 0x54);
+    assert!(takeoff.try_wait().is_none());
+
+    // Ignore the first attempt and answer the retry, first with an ack for another sequence
+    let mut request = [0u8; 64];
+    let num_bytes = drone.recv(&mut request).unwrap();
+    let first = TelloGram::parse(&request[..num_bytes]).unwrap();
+    let num_bytes = drone.recv(&mut request).unwrap();
+    let retry = TelloGram::parse(&request[..num_bytes]).unwrap();
+    assert_eq!(first, retry);
+    assert_eq!(retry.sequence(), takeoff.sequence());
+
+    let wrong_ack = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Set, 0, 0x54, retry.sequence().wrapping_add(1), &[0]);
+    drone.send_to(&wrong_ack.to_bytes(), client).unwrap();
+    let ack = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Set, 0, 0x54, retry.sequence(), &[0]);
+    drone.send_to(&ack.to_bytes(), client).unwrap();
+
+    assert!(takeoff.wait().is_ok());
+}
+
+#[test]
+fn test_command_fails_without_ack() {
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .video_port(9045)
+        .ack_timeout(Duration::from_millis(50))
+        .ack_retries(2);
+    let (tello, _) = connect_to_stand_in(&drone, config);
+
+    let landing = tello.land().unwrap();
+    let sequence = landing.sequence();
+    match landing.wait() {
+        Err(TelloError::NoAck { id: 0x55, sequence: unanswered }) => assert_eq!(unanswered, sequence),
+        other => panic!("Expected a missing ack, got {:?}", other),
+    }
+
+    // The first attempt and both retries reached the drone
+    let mut request = [0u8; 64];
+    for _ in 0..3 {
+        let num_bytes = drone.recv(&mut request).unwrap();
+        assert_eq!(TelloGram::parse(&request[..num_bytes]).unwrap().sequence(), sequence);
+    }
+}
+
+#[test]
+fn test_drone_control_waits_for_ack() {
+    use crate::protocol::{ TelloGramDirection, PackageType };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .video_port(9046)
+        .ack_timeout(Duration::from_millis(100))
+        .ack_retries(3);
+    let (tello, client) = connect_to_stand_in(&drone, config);
+
+    let stand_in = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let num_bytes = drone.recv(&mut request).unwrap();
+        let flip = TelloGram::parse(&request[..num_bytes]).unwrap();
+        let ack = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Set, 0, flip.id(), flip.sequence(), &[0]);
+        drone.send_to(&ack.to_bytes(), client).unwrap();
+        flip
+    });
+
+    let mission: &dyn DroneControl = &tello;
+    mission.flip(FlipDirection::Left).unwrap();
+    assert_eq!(stand_in.join().unwrap().payload(), &[FlipDirection::Left as u8]);
+}
+
+#[test]
+fn test_emergency_resent_until_acked() {
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .video_port(9047)
+        .ack_timeout(Duration::from_millis(50));
+    let (tello, client) = connect_to_stand_in(&drone, config);
+
+    let emergency = tello.emergency().unwrap();
+
+    // Enter SDK mode on the first request, then leave the first emergency stop unanswered
+    let mut received = vec![];
+    let mut request = [0u8; 64];
+    while received.len() < 3 {
+        let num_bytes = drone.recv(&mut request).unwrap();
+        let text = String::from_utf8_lossy(&request[..num_bytes]).to_string();
+        if text == "command" && received.is_empty() {
+            drone.send_to(b"ok", client).unwrap();
+        }
+        if text == "emergency" && received.iter().any(|request| request == "emergency") {
+            drone.send_to(b"ok", client).unwrap();
+        }
+        received.push(text);
+    }
+
+    assert!(emergency.wait().is_ok());
+    assert_eq!(received, vec!["command", "emergency", "emergency"]);
+}
+
+#[test]
+fn test_speed_mode_in_stick_packets() {
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let (tello, _) = connect_to_stand_in(&drone, TelloConfig::new().video_port(9048));
+
+    let (sender, receiver) = channel();
+    tello.set_telemetry_listener(sender);
+    assert_eq!(tello.speed_mode(), SpeedMode::Slow);
+
+    let mut request = [0u8; 64];
+    let mut stick_speed_mode = || {
+        tello.set_joystick(controller::State {
+            joystick_left_x: 0.0,
+            joystick_left_y: 0.0,
+            joystick_right_x: 0.0,
+            joystick_right_y: 0.0
+        }).unwrap();
+        let num_bytes = drone.recv(&mut request).unwrap();
+        TelloGram::joystick_speed_mode(TelloGram::parse(&request[..num_bytes]).unwrap().payload())
+    };
+
+    assert_eq!(stick_speed_mode(), Some(SpeedMode::Slow));
+    assert_eq!(tello.toggle_speed_mode(), SpeedMode::Fast);
+    assert_eq!(stick_speed_mode(), Some(SpeedMode::Fast));
+    assert_eq!(stick_speed_mode(), Some(SpeedMode::Fast));
+
+    tello.set_speed_mode(SpeedMode::Fast);
+    tello.set_speed_mode(SpeedMode::Slow);
+    assert_eq!(stick_speed_mode(), Some(SpeedMode::Slow));
+
+    // Only actual changes are published
+    let published: Vec<_> = receiver.try_iter().collect();
+    assert!(matches!(published[..], [Telemetry::SpeedMode(SpeedMode::Fast), Telemetry::SpeedMode(SpeedMode::Slow)]));
+}
+
+#[test]
+fn test_drone_settings() {
+    use crate::protocol::{ TelloGramDirection, PackageType };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .video_port(9049)
+        .ack_timeout(Duration::from_millis(100));
+    let (tello, client) = connect_to_stand_in(&drone, config);
+
+    let stand_in = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let mut respond = |payload: &[u8]| {
+            let num_bytes = drone.recv(&mut request).unwrap();
+            let gram = TelloGram::parse(&request[..num_bytes]).unwrap();
+            let response = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Get, 0, gram.id(), gram.sequence(), payload);
+            drone.send_to(&response.to_bytes(), client).unwrap();
+            gram
+        };
+
+        let mut attitude = vec![0];
+        attitude.extend_from_slice(&25f32.to_le_bytes());
+        vec![
+            respond(&[0, 0x1e, 0x00]),
+            respond(&[0]),
+            respond(&[1]),
+            respond(&[0, 15]),
+            respond(&[0]),
+            respond(&attitude),
+            respond(&[0, 0x1e]),
+        ]
+    });
+
+    assert_eq!(tello.get_max_height().unwrap(), 30);
+    tello.set_max_height(10).unwrap();
+    assert!(matches!(tello.set_low_battery_threshold(5), Err(TelloError::Rejected { id: 0x1055, code: 1 })));
+    assert_eq!(tello.get_low_battery_threshold().unwrap(), 15);
+    tello.set_attitude_angle(20.0).unwrap();
+    assert_eq!(tello.get_attitude_angle().unwrap(), 25.0);
+    assert!(matches!(tello.get_max_height(), Err(TelloError::InvalidResponse { id: 0x1056, .. })));
+
+    let ids: Vec<_> = stand_in.join().unwrap().iter().map(|gram| gram.id()).collect();
+    assert_eq!(ids, vec![0x1056, 0x58, 0x1055, 0x1057, 0x1058, 0x1059, 0x1056]);
+}
+
+#[test]
+fn test_versions_and_wifi_strength() {
+    use crate::protocol::{ TelloGramDirection, PackageType };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .video_port(9050)
+        .ack_timeout(Duration::from_millis(100));
+    let (tello, client) = connect_to_stand_in(&drone, config);
+
+    let (sender, receiver) = channel();
+    tello.set_telemetry_listener(sender);
+
+    let stand_in = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        for version in [&b"\x0001.04.92.01\x00\x00\x00"[..], &b"\x0001.00.00.10"[..]].iter() {
+            let num_bytes = drone.recv(&mut request).unwrap();
+            let gram = TelloGram::parse(&request[..num_bytes]).unwrap();
+            let response = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Get, 0, gram.id(), gram.sequence(), version);
+            drone.send_to(&response.to_bytes(), client).unwrap();
+        }
+
+        let wifi = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Data1, 0, 0x1a, 0, &[87, 3]);
+        drone.send_to(&wifi.to_bytes(), client).unwrap();
+    });
+
+    assert_eq!(tello.get_version().unwrap(), "01.04.92.01");
+    assert_eq!(tello.get_loader_version().unwrap(), "01.00.00.10");
+    stand_in.join().unwrap();
+
+    let published_version = receiver.recv_timeout(Duration::from_secs(2));
+    assert!(matches!(published_version, Ok(Telemetry::FirmwareVersion(version)) if version == "01.04.92.01"));
+    assert!(wait_for_telemetry(&receiver, |telemetry| matches!(telemetry, Telemetry::Wifi(WifiStrength { strength: 87, interference: 3 }))));
+    assert_eq!(tello.wifi_strength(), Some(WifiStrength { strength: 87, interference: 3 }));
+}
+
+#[test]
+fn test_take_picture_with_missing_chunk() {
+    use crate::protocol::{ TelloGramDirection, PackageType };
+    use crate::file_transfer::{ self, CHUNK_SIZE };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .video_port(9051)
+        .ack_timeout(Duration::from_millis(50));
+    let (tello, client) = connect_to_stand_in(&drone, config);
+
+    let picture: Vec<u8> = (0..2500).map(|i| (i % 253) as u8).collect();
+    let info = FileInfo { file_type: 1, size: picture.len() as u32, file_id: 4 };
+    let expected_picture = picture.clone();
+
+    let stand_in = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let receive = |request: &mut [u8]| {
+            let num_bytes = drone.recv(request).unwrap();
+            TelloGram::parse(&request[..num_bytes]).unwrap()
+        };
+        let send = |id: u16, sequence: u16, payload: &[u8]| {
+            let gram = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Data1, 0, id, sequence, payload);
+            drone.send_to(&gram.to_bytes(), client).unwrap();
+        };
+        let send_chunk = |chunk: usize| {
+            let data = picture.chunks(CHUNK_SIZE).nth(chunk).unwrap().to_vec();
+            send(0x63, 0, &FileChunk { file_id: 4, piece: 0, chunk: chunk as u32, data }.to_bytes());
+        };
+
+        let take_picture = receive(&mut request);
+        assert_eq!(take_picture.id(), 0x30);
+        send(0x30, take_picture.sequence(), &[0]);
+
+        send(0x62, 0, &info.to_bytes());
+        let size_ack = receive(&mut request);
+        assert_eq!((size_ack.id(), size_ack.payload()), (0x62, &[0][..]));
+
+        // Out of order and without the middle chunk, which has to be asked for again
+        send_chunk(2);
+        send_chunk(0);
+        assert_eq!(receive(&mut request).id(), 0x62);
+        send_chunk(1);
+
+        let mut replies = vec![];
+        while replies.len() < 2 {
+            let reply = receive(&mut request);
+            if reply.id() != 0x62 {
+                replies.push((reply.id(), reply.payload().to_vec()));
+            }
+        }
+        replies
+    });
+
+    assert_eq!(tello.take_picture().unwrap(), expected_picture);
+    assert_eq!(stand_in.join().unwrap(), vec![
+        (0x63, file_transfer::piece_ack(4, 0, true)),
+        (0x64, file_transfer::file_done(4, 2500)),
+    ]);
+}
+
+#[test]
+fn test_video_settings() {
+    use crate::protocol::{ TelloGramDirection, PackageType };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let config = TelloConfig::new()
+        .video_port(9052)
+        .ack_timeout(Duration::from_millis(100));
+    let (tello, client) = connect_to_stand_in(&drone, config);
+
+    let stand_in = thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let mut received = vec![];
+        while received.len() < 4 {
+            let num_bytes = drone.recv(&mut request).unwrap();
+            let gram = TelloGram::parse(&request[..num_bytes]).unwrap();
+            if gram.id() != 0x25 {
+                let ack = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Set, 0, gram.id(), gram.sequence(), &[0]);
+                drone.send_to(&ack.to_bytes(), client).unwrap();
             }
+            received.push((gram.id(), gram.payload().to_vec()));
         }
+        received
     });
 
-    player.run();
+    assert_eq!(tello.video_mode(), VideoMode::Normal);
+    tello.set_video_bitrate(VideoBitrate::Mbps2).unwrap();
+    tello.set_video_mode(VideoMode::Wide).unwrap();
+    tello.set_exposure(Exposure::Low).unwrap();
+    assert_eq!(tello.video_mode(), VideoMode::Wide);
+
+    // Switching the mode asks for the new stream header right away
+    assert_eq!(stand_in.join().unwrap(), vec![
+        (0x20, vec![3]),
+        (0x31, vec![1]),
+        (0x25, vec![]),
+        (0x34, vec![0]),
+    ]);
+}
+
+#[test]
+fn test_invalid_packages() {
+    use crate::protocol::{ TelloGramDirection, PackageType };
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    drone.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let (tello, client) = connect_to_stand_in(&drone, TelloConfig::new().video_port(9053));
+
+    let (sender, receiver) = channel();
+    tello.set_telemetry_listener(sender);
+
+    assert!(matches!(tello.send_raw(&[0xcc, 0x58, 0x00]), Err(TelloError::Protocol(ParseError::TooShort(3)))));
 
-    is_running.store(false, Ordering::Relaxed);
+    let mut corrupted = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Get, 0, 0x56, 0, &[0; 24]).to_bytes();
+    corrupted[12] ^= 0xff;
+    drone.send_to(&corrupted, client).unwrap();
+    assert!(wait_for_telemetry(&receiver, |telemetry| matches!(telemetry, Telemetry::InvalidPackage(ParseError::InvalidCrc16 { .. }))));
 
-    tello_cmd_loop.join().unwrap();
-    controller_thread.join().unwrap();
+    let truncated = TelloGram::new(TelloGramDirection::FromDrone, PackageType::Get, 0, 0x56, 0, &[0; 3]);
+    drone.send_to(&truncated.to_bytes(), client).unwrap();
+    assert!(wait_for_telemetry(&receiver, |telemetry| {
+        matches!(telemetry, Telemetry::InvalidPackage(ParseError::TruncatedPayload { id: 0x56, length: 3 }))
+    }));
 }
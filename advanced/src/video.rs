@@ -0,0 +1,648 @@
+use gst::prelude::*;
+
+use std::cmp;
+use std::fmt;
+use std::fs::File;
+use std::io::{ self, BufWriter, Read };
+use std::error::Error;
+use std::net::UdpSocket;
+use std::path::Path;
+use std::thread;
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ channel, Sender };
+use std::time::{ Duration, Instant };
+
+use crate::player;
+use crate::video_capture::{ CaptureReader, CaptureWriter };
+
+const RECORDING_TEE: &str = "recording-tee";
+const FINALIZE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum VideoError {
+    Init(gst::glib::Error),
+    Element(&'static str, gst::glib::BoolError),
+    Pipeline(gst::glib::BoolError),
+    Cast(&'static str),
+    StateChange(gst::StateChangeError),
+    Network(io::Error),
+    InvalidFrame(&'static str),
+    NoPad(&'static str),
+    Link(gst::PadLinkError),
+    AlreadyRecording,
+    RecordingNotFinalized,
+    AlreadyCapturing,
+    Capture(io::Error),
+    InvalidReplaySpeed(f32),
+}
+
+impl fmt::Display for VideoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VideoError::Init(e) => write!(f, "Failed to init gstreamer: {}", e),
+            VideoError::Element(name, e) => write!(f, "Failed to create {}: {}", name, e),
+            VideoError::Pipeline(e) => write!(f, "Failed to build video pipeline: {}", e),
+            VideoError::Cast(name) => write!(f, "Pipeline element is not an {}", name),
+            VideoError::StateChange(e) => write!(f, "Failed to change pipeline state: {}", e),
+            VideoError::Network(e) => write!(f, "Failed to set up video socket: {}", e),
+            VideoError::InvalidFrame(reason) => write!(f, "Invalid video frame: {}", reason),
+            VideoError::NoPad(name) => write!(f, "Pipeline element has no {} pad", name),
+            VideoError::Link(e) => write!(f, "Failed to link recording branch: {}", e),
+            VideoError::AlreadyRecording => write!(f, "Video is already being recorded"),
+            VideoError::RecordingNotFinalized => write!(f, "Recording was not finalized in time and may be unplayable"),
+            VideoError::AlreadyCapturing => write!(f, "Video packets are already being captured"),
+            VideoError::Capture(e) => write!(f, "Failed to write video capture: {}", e),
+            VideoError::InvalidReplaySpeed(speed) => write!(f, "Replay speed must be positive, got {}", speed),
+        }
+    }
+}
+
+impl Error for VideoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            VideoError::Init(e) => Some(e),
+            VideoError::Element(_, e) => Some(e),
+            VideoError::Pipeline(e) => Some(e),
+            VideoError::StateChange(e) => Some(e),
+            VideoError::Network(e) => Some(e),
+            VideoError::Link(e) => Some(e),
+            VideoError::Capture(e) => Some(e),
+            VideoError::Cast(_) |
+            VideoError::InvalidFrame(_) |
+            VideoError::NoPad(_) |
+            VideoError::AlreadyRecording |
+            VideoError::RecordingNotFinalized |
+            VideoError::AlreadyCapturing |
+            VideoError::InvalidReplaySpeed(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for VideoError {
+    fn from(e: io::Error) -> VideoError {
+        VideoError::Network(e)
+    }
+}
+
+fn make(name: &'static str) -> Result<gst::Element, VideoError> {
+    gst::ElementFactory::make(name, None).map_err(|e| VideoError::Element(name, e))
+}
+
+// Decodes the raw H.264 stream pushed into the appsrc into RGBA frames pulled from the appsink.
+// The parsed stream passes a tee on the way, so it can be recorded without decoding it twice.
+pub fn initialize_decoder() -> Result<(gst::Pipeline, gst_app::AppSrc, gst_app::AppSink), VideoError> {
+    gst::init().map_err(VideoError::Init)?;
+
+    let pipeline = gst::Pipeline::new(None);
+    let source = make("appsrc")?;
+    let h264parse = make("h264parse")?;
+    let tee = gst::ElementFactory::make("tee", Some(RECORDING_TEE)).map_err(|e| VideoError::Element("tee", e))?;
+    let queue = make("queue")?;
+    let avdec_h264 = make("avdec_h264")?;
+    let videoconvert = make("videoconvert")?;
+    let sink = make("appsink")?;
+
+    let elements = [&source, &h264parse, &tee, &queue, &avdec_h264, &videoconvert, &sink];
+    pipeline.add_many(&elements).map_err(VideoError::Pipeline)?;
+    gst::Element::link_many(&elements).map_err(VideoError::Pipeline)?;
+
+    let appsource = source.dynamic_cast::<gst_app::AppSrc>().map_err(|_| VideoError::Cast("appsrc"))?;
+    let appsink = sink.dynamic_cast::<gst_app::AppSink>().map_err(|_| VideoError::Cast("appsink"))?;
+
+    appsource.set_latency(gst::ClockTime::from_mseconds(0), gst::ClockTime::from_mseconds(10));
+    appsource.set_property_is_live(true);
+    appsource.set_stream_type(gst_app::AppStreamType::Stream);
+    // Buffers are stamped with their arrival time, which is all the timing a recording gets
+    appsource.set_property_format(gst::Format::Time);
+    appsource.set_property("do-timestamp", &true).map_err(VideoError::Pipeline)?;
+
+    appsink.set_caps(Some(&gst::Caps::new_simple(
+        "video/x-raw",
+        &[
+            ("format", &"RGBA")
+        ]
+    )));
+
+    pipeline.set_state(gst::State::Playing).map_err(VideoError::StateChange)?;
+
+    Ok((pipeline, appsource, appsink))
+}
+
+// Only packed formats, where every pixel takes the same number of bytes in a single plane
+fn bytes_per_pixel(format: &str) -> Option<usize> {
+    match format {
+        "RGBA" | "BGRA" | "ARGB" | "ABGR" | "RGBx" | "BGRx" | "xRGB" | "xBGR" => Some(4),
+        "RGB" | "BGR" => Some(3),
+        "GRAY8" => Some(1),
+        _ => None
+    }
+}
+
+// Layout of the decoded frames as negotiated by the decoder, which renegotiates whenever the
+// stream changes resolution, e.g. when switching between the 4:3 and 16:9 video modes
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameLayout {
+    pub width: u32,
+    pub height: u32,
+    pub format: String,
+    pub bytes_per_pixel: usize,
+    pub stride: usize,
+}
+
+impl FrameLayout {
+    // Buffers without video meta follow the stride GStreamer derives from the caps
+    pub fn from_caps(caps: &gst::CapsRef) -> Option<FrameLayout> {
+        let info = gst_video::VideoInfo::from_caps(caps).ok()?;
+        let stride = *info.stride().first()?;
+        if stride < 0 {
+            return None;
+        }
+        FrameLayout::new(info.width(), info.height(), info.format().to_str(), stride as usize)
+    }
+
+    pub fn new(width: u32, height: u32, format: &str, stride: usize) -> Option<FrameLayout> {
+        let bytes_per_pixel = bytes_per_pixel(format)?;
+        if stride < width as usize * bytes_per_pixel {
+            return None;
+        }
+
+        Some(FrameLayout {
+            width,
+            height,
+            format: format.to_string(),
+            bytes_per_pixel,
+            stride,
+        })
+    }
+
+    // Strips the row padding, returns None when the data is too short for the layout
+    pub fn pack_rows(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let row_size = self.width as usize * self.bytes_per_pixel;
+        let height = self.height as usize;
+        if height == 0 {
+            return Some(vec![]);
+        }
+        if data.len() < self.stride * (height - 1) + row_size {
+            return None;
+        }
+
+        Some(data.chunks(self.stride).take(height).flat_map(|row| &row[..row_size]).cloned().collect())
+    }
+}
+
+// Converts a decoded sample into the tightly packed RGBA frame the player uploads as is
+pub fn decode_frame(sample: &gst::Sample) -> Result<player::Frame, VideoError> {
+    let mut layout = sample.get_caps()
+        .and_then(FrameLayout::from_caps)
+        .ok_or(VideoError::InvalidFrame("caps without size or known format"))?;
+    if layout.format != "RGBA" {
+        return Err(VideoError::InvalidFrame("not in RGBA"));
+    }
+
+    let buffer = sample.get_buffer().ok_or(VideoError::InvalidFrame("no buffer"))?;
+    // Decoders using their own alignment describe it in the video meta of each buffer
+    let mut offset = 0;
+    if let Some(meta) = buffer.get_meta::<gst_video::VideoMeta>() {
+        match (meta.get_stride().first(), meta.get_offset().first()) {
+            (Some(&stride), Some(&plane_offset)) if stride as usize >= layout.width as usize * layout.bytes_per_pixel => {
+                layout.stride = stride as usize;
+                offset = plane_offset;
+            },
+            _ => return Err(VideoError::InvalidFrame("video meta with an invalid stride"))
+        }
+    }
+
+    let map = buffer.map_readable().map_err(|_| VideoError::InvalidFrame("buffer not readable"))?;
+    let data = map.as_slice().get(offset..)
+        .and_then(|data| layout.pack_rows(data))
+        .ok_or(VideoError::InvalidFrame("buffer smaller than its caps"))?;
+
+    Ok(player::Frame {
+        width: layout.width,
+        height: layout.height,
+        data
+    })
+}
+
+type SharedCapture = Arc<Mutex<Option<CaptureWriter<BufWriter<File>>>>>;
+
+fn push_packet(appsource: &gst_app::AppSrc, packet: &[u8], header_len: usize) {
+    if packet.len() < header_len {
+        println!("Received video package without header {:?}", packet);
+        return;
+    }
+
+    if let Err(e) = appsource.push_buffer(gst::buffer::Buffer::from_slice(packet[header_len..].to_vec())) {
+        println!("Failed to push video buffer: {:?}", e);
+    }
+}
+
+// Receives H.264 over UDP and forwards the decoded frames until dropped. The binary protocol
+// prefixes every video datagram with a 2 byte header, the text SDK sends the bare stream.
+pub struct VideoStream {
+    is_running: Arc<AtomicBool>,
+    pipeline: gst::Pipeline,
+    tee: gst::Element,
+    header_len: usize,
+    recording: Mutex<Option<Recording>>,
+    capture: SharedCapture,
+    raw_receive_thread: Option<thread::JoinHandle<()>>,
+    frame_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl VideoStream {
+    pub fn start(socket: UdpSocket, header_len: usize, poll_interval: Duration, frame_channel: Sender<player::Frame>) -> Result<VideoStream, VideoError> {
+        socket.set_read_timeout(Some(poll_interval))?;
+
+        VideoStream::from_source(header_len, poll_interval, frame_channel, move |is_running, appsource, capture| {
+            let mut buffer = [0; 4096];
+            while (*is_running).load(Ordering::Relaxed) {
+                match socket.recv(&mut buffer) {
+                    Ok(num_bytes) => {
+                        if let Some(capture) = capture.lock().unwrap().as_mut() {
+                            if let Err(e) = capture.write_packet(&buffer[..num_bytes]) {
+                                println!("Failed to capture video packet: {}", e);
+                            }
+                        }
+                        push_packet(&appsource, &buffer[..num_bytes], header_len);
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => (),
+                    Err(e) => println!("Failed to receive video buffer: {}", e)
+                }
+            }
+        })
+    }
+
+    // Feeds a capture through the decoder with the packets spaced as they arrived, divided by
+    // the speed. The decoder is flushed at the end, so the last frame comes out as well.
+    pub fn replay<R: Read + Send + 'static>(capture: CaptureReader<R>, speed: f32, poll_interval: Duration, frame_channel: Sender<player::Frame>) -> Result<VideoStream, VideoError> {
+        if !(speed > 0.0 && speed.is_finite()) {
+            return Err(VideoError::InvalidReplaySpeed(speed));
+        }
+
+        let header_len = capture.header_len();
+        VideoStream::from_source(header_len, poll_interval, frame_channel, move |is_running, appsource, _| {
+            let started = Instant::now();
+            for packet in capture {
+                let packet = match packet {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        println!("Failed to read video capture: {}", e);
+                        break;
+                    }
+                };
+
+                // Waits in small steps, so dropping the stream stops the replay promptly
+                let due = started + packet.arrival.div_f32(speed);
+                while (*is_running).load(Ordering::Relaxed) && Instant::now() < due {
+                    thread::sleep(cmp::min(due.saturating_duration_since(Instant::now()), poll_interval));
+                }
+                if !(*is_running).load(Ordering::Relaxed) {
+                    return;
+                }
+
+                push_packet(&appsource, &packet.payload, header_len);
+            }
+
+            if let Err(e) = appsource.end_of_stream() {
+                println!("Failed to end replay: {:?}", e);
+            }
+        })
+    }
+
+    fn from_source<F>(header_len: usize, poll_interval: Duration, frame_channel: Sender<player::Frame>, source: F) -> Result<VideoStream, VideoError>
+        where F: FnOnce(Arc<AtomicBool>, gst_app::AppSrc, SharedCapture) + Send + 'static {
+        let (pipeline, appsource, appsink) = initialize_decoder()?;
+        let tee = pipeline.get_by_name(RECORDING_TEE).ok_or(VideoError::Cast("tee"))?;
+
+        let is_running = Arc::new(AtomicBool::new(true));
+        let capture = Arc::new(Mutex::new(None));
+
+        let raw_receive_thread_running = is_running.clone();
+        let raw_receive_thread_capture = capture.clone();
+        let raw_receive_thread = Some(thread::spawn(move || {
+            source(raw_receive_thread_running, appsource, raw_receive_thread_capture)
+        }));
+
+        let frame_thread_running = is_running.clone();
+        let pull_timeout = gst::ClockTime::from_mseconds(poll_interval.as_millis() as u64);
+        let frame_thread = Some(thread::spawn(move || {
+            let mut current_size = None;
+            while (*frame_thread_running).load(Ordering::Relaxed) {
+                match appsink.try_pull_sample(pull_timeout) {
+                    Some(sample) => {
+                        let frame = match decode_frame(&sample) {
+                            Ok(frame) => frame,
+                            Err(e) => {
+                                println!("Dropping video frame: {}", e);
+                                continue;
+                            }
+                        };
+                        if current_size != Some((frame.width, frame.height)) {
+                            println!("Video resolution is {}x{}", frame.width, frame.height);
+                            current_size = Some((frame.width, frame.height));
+                        }
+
+                        if frame_channel.send(frame).is_err() {
+                            // The player has gone away, so there is nobody to decode frames for
+                            break;
+                        }
+                    },
+                    None => ()
+                }
+            }
+        }));
+
+        Ok(VideoStream {
+            is_running,
+            pipeline,
+            tee,
+            header_len,
+            recording: Mutex::new(None),
+            capture,
+            raw_receive_thread,
+            frame_thread,
+        })
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.lock().unwrap().is_some()
+    }
+
+    // Dumps every video packet exactly as received, header included, for replaying later
+    pub fn start_capture(&self, path: &Path) -> Result<(), VideoError> {
+        let mut capture = self.capture.lock().unwrap();
+        if capture.is_some() {
+            return Err(VideoError::AlreadyCapturing);
+        }
+
+        *capture = Some(CaptureWriter::create(path, self.header_len).map_err(VideoError::Capture)?);
+        Ok(())
+    }
+
+    pub fn stop_capture(&self) -> Result<(), VideoError> {
+        match self.capture.lock().unwrap().take() {
+            Some(capture) => capture.into_inner().map(|_| ()).map_err(VideoError::Capture),
+            None => Ok(())
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.lock().unwrap().is_some()
+    }
+
+    // Records the stream without re-encoding it, to MP4 if the path ends in .mp4 and to
+    // Matroska otherwise. The file starts at the next key frame.
+    pub fn start_recording(&self, path: &Path) -> Result<(), VideoError> {
+        let mut recording = self.recording.lock().unwrap();
+        if recording.is_some() {
+            return Err(VideoError::AlreadyRecording);
+        }
+
+        let muxer = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("mp4") => "mp4mux",
+            _ => "matroskamux"
+        };
+        let queue = make("queue")?;
+        // Converts the byte stream into the format the muxer wants, the decoder branch keeps the byte stream
+        let h264parse = make("h264parse")?;
+        let mux = make(muxer)?;
+        let filesink = make("filesink")?;
+        filesink.set_property("location", &path.to_string_lossy().to_string()).map_err(VideoError::Pipeline)?;
+
+        let bin = gst::Bin::new(None);
+        bin.add_many(&[&queue, &h264parse, &mux, &filesink]).map_err(VideoError::Pipeline)?;
+        gst::Element::link_many(&[&queue, &h264parse, &mux, &filesink]).map_err(VideoError::Pipeline)?;
+        let queue_sink = queue.get_static_pad("sink").ok_or(VideoError::NoPad("queue sink"))?;
+        let bin_sink = gst::GhostPad::new(Some("sink"), &queue_sink).map_err(VideoError::Pipeline)?;
+        bin_sink.set_active(true).map_err(VideoError::Pipeline)?;
+        bin.add_pad(&bin_sink).map_err(VideoError::Pipeline)?;
+
+        self.pipeline.add(&bin).map_err(VideoError::Pipeline)?;
+        let tee_pad = match self.tee.get_request_pad("src_%u") {
+            Some(tee_pad) => tee_pad,
+            None => {
+                self.remove_branch(&bin)?;
+                return Err(VideoError::NoPad("tee request"));
+            }
+        };
+
+        // Muxers can only start a file from a key frame
+        tee_pad.add_probe(gst::PadProbeType::BUFFER, |_, info| match &info.data {
+            Some(gst::PadProbeData::Buffer(buffer)) if buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT) => gst::PadProbeReturn::Drop,
+            _ => gst::PadProbeReturn::Remove
+        });
+
+        let linked = tee_pad.link(&bin_sink).map_err(VideoError::Link)
+            .and_then(|_| bin.sync_state_with_parent().map_err(VideoError::Pipeline));
+        if let Err(e) = linked {
+            self.tee.release_request_pad(&tee_pad);
+            self.remove_branch(&bin)?;
+            return Err(e);
+        }
+
+        *recording = Some(Recording { bin, tee_pad, filesink });
+        Ok(())
+    }
+
+    // Sends an EOS through the recording branch only, so the muxer writes out its headers
+    // and index while the decoder keeps running, and waits for it to reach the file
+    pub fn stop_recording(&self) -> Result<(), VideoError> {
+        let recording = match self.recording.lock().unwrap().take() {
+            Some(recording) => recording,
+            None => return Ok(())
+        };
+
+        let (finalized_sender, finalized_receiver) = channel();
+        let finalized_sender = Mutex::new(finalized_sender);
+        let filesink_pad = recording.filesink.get_static_pad("sink").ok_or(VideoError::NoPad("filesink sink"))?;
+        filesink_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gst::PadProbeData::Event(event)) = &info.data {
+                if event.get_type() == gst::EventType::Eos {
+                    let _ = finalized_sender.lock().unwrap().send(());
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        // Unlinked in between buffers, so the muxer never gets half an access unit
+        let bin_sink = recording.bin.get_static_pad("sink").ok_or(VideoError::NoPad("recording sink"))?;
+        recording.tee_pad.add_probe(gst::PadProbeType::IDLE, move |tee_pad, _| {
+            if tee_pad.unlink(&bin_sink).is_err() {
+                println!("Failed to unlink recording branch");
+            }
+            bin_sink.send_event(gst::Event::new_eos().build());
+            gst::PadProbeReturn::Remove
+        });
+
+        let is_finalized = finalized_receiver.recv_timeout(FINALIZE_TIMEOUT).is_ok();
+        self.tee.release_request_pad(&recording.tee_pad);
+        self.remove_branch(&recording.bin)?;
+
+        if is_finalized {
+            Ok(())
+        } else {
+            Err(VideoError::RecordingNotFinalized)
+        }
+    }
+
+    fn remove_branch(&self, bin: &gst::Bin) -> Result<(), VideoError> {
+        bin.set_state(gst::State::Null).map_err(VideoError::StateChange)?;
+        self.pipeline.remove(bin).map_err(VideoError::Pipeline)
+    }
+}
+
+struct Recording {
+    bin: gst::Bin,
+    tee_pad: gst::Pad,
+    filesink: gst::Element,
+}
+
+impl Drop for VideoStream {
+    fn drop(&mut self) {
+        // Stopping the pipeline first would leave the file without its headers and index
+        if let Err(e) = self.stop_recording() {
+            println!("Failed to finalize recording: {}", e);
+        }
+        if let Err(e) = self.stop_capture() {
+            println!("Failed to finish video capture: {}", e);
+        }
+        self.is_running.store(false, Ordering::Relaxed);
+
+        if let Err(e) = self.pipeline.set_state(gst::State::Null) {
+            println!("Failed to stop video pipeline: {:?}", e);
+        }
+
+        for thread in [self.frame_thread.take(), self.raw_receive_thread.take()].iter_mut() {
+            if let Some(thread) = thread.take() {
+                if thread.join().is_err() {
+                    println!("Video worker thread panicked");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pack_padded_rows() {
+    // 3 RGB pixels take 9 bytes, padded to 16 as a decoder aligning rows to 16 bytes would
+    let layout = FrameLayout::new(3, 2, "RGB", 16).unwrap();
+    assert_eq!((layout.bytes_per_pixel, layout.stride), (3, 16));
+    assert_eq!(FrameLayout::new(3, 2, "RGB", 8), None);
+    assert_eq!(FrameLayout::new(3, 2, "I420", 16), None);
+
+    let padded: Vec<u8> = (0..25).collect();
+    assert_eq!(layout.pack_rows(&padded), Some(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 16, 17, 18, 19, 20, 21, 22, 23, 24]));
+    assert_eq!(layout.pack_rows(&padded[..24]), None);
+}
+
+#[test]
+fn test_stride_from_caps() {
+    gst::init().unwrap();
+
+    // Rows of 3 RGB pixels are 9 bytes, which GStreamer pads to 12 by default
+    let caps = gst::Caps::builder("video/x-raw")
+        .field("format", &"RGB")
+        .field("width", &3i32)
+        .field("height", &2i32)
+        .field("framerate", &gst::Fraction::new(30, 1))
+        .build();
+    let layout = FrameLayout::from_caps(&caps).unwrap();
+    assert_eq!(layout, FrameLayout { width: 3, height: 2, format: String::from("RGB"), bytes_per_pixel: 3, stride: 12 });
+
+    // A buffer laid out with a 16 byte stride by a decoder says so in its video meta
+    let caps = gst::Caps::builder("video/x-raw")
+        .field("format", &"RGBA")
+        .field("width", &3i32)
+        .field("height", &2i32)
+        .field("framerate", &gst::Fraction::new(30, 1))
+        .build();
+    let mut buffer = gst::Buffer::from_slice((0..32).collect::<Vec<u8>>());
+    gst_video::VideoMeta::add_full(
+        buffer.get_mut().unwrap(), gst_video::VideoFrameFlags::NONE, gst_video::VideoFormat::Rgba, 3, 2, &[0], &[16]
+    );
+    let sample = gst::Sample::new().caps(&caps).buffer(&buffer).build();
+    let frame = decode_frame(&sample).unwrap();
+    assert_eq!(&frame.data[..12], &(0..12).collect::<Vec<u8>>()[..]);
+    assert_eq!(&frame.data[12..], &(16..28).collect::<Vec<u8>>()[..]);
+}
+
+#[test]
+fn test_decoded_frame_geometry() {
+    // Three intra coded 80x48 frames, far from the 960x720 the drone streams by default
+    let clip = include_bytes!("test_clip_80x48.h264");
+
+    let (pipeline, appsource, appsink) = initialize_decoder().unwrap();
+    appsource.push_buffer(gst::buffer::Buffer::from_slice(clip.to_vec())).unwrap();
+    appsource.end_of_stream().unwrap();
+
+    let sample = appsink.try_pull_sample(gst::ClockTime::from_seconds(5)).expect("Decoder produced no frame");
+    let layout = sample.get_caps().and_then(FrameLayout::from_caps).unwrap();
+    assert_eq!(layout, FrameLayout { width: 80, height: 48, format: String::from("RGBA"), bytes_per_pixel: 4, stride: 320 });
+
+    let frame = decode_frame(&sample).unwrap();
+    assert_eq!((frame.width, frame.height), (80, 48));
+    assert_eq!(frame.data.len(), 80 * 48 * 4);
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn test_record_stream() {
+    use std::fs;
+    use std::sync::mpsc::channel;
+
+    let path = std::env::temp_dir().join("tello-test-recording.mkv");
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    let (frame_sender, frame_receiver) = channel();
+    let stream = VideoStream::start(socket, 0, Duration::from_millis(20), frame_sender).unwrap();
+
+    stream.start_recording(&path).unwrap();
+    assert!(stream.is_recording());
+    assert!(matches!(stream.start_recording(&path), Err(VideoError::AlreadyRecording)));
+
+    let drone = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for packet in include_bytes!("test_clip_80x48.h264").chunks(1400) {
+        drone.send_to(packet, address).unwrap();
+    }
+    frame_receiver.recv_timeout(Duration::from_secs(5)).expect("Decoder produced no frame");
+
+    stream.stop_recording().unwrap();
+    assert!(!stream.is_recording());
+
+    // Matroska files start with an EBML header
+    let recorded = fs::read(&path).unwrap();
+    assert!(recorded.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_replay_capture() {
+    use std::sync::mpsc::channel;
+
+    // The packets arrived 20 ms apart, so the replay at four times the speed takes about 60 ms
+    let mut writer = CaptureWriter::new(vec![], 2).unwrap();
+    for (index, packet) in include_bytes!("test_clip_80x48.h264").chunks(1400).enumerate() {
+        let mut payload = vec![0, index as u8];
+        payload.extend_from_slice(packet);
+        writer.write_packet_at(Duration::from_millis(100) * index as u32 / 5, &payload).unwrap();
+    }
+    let capture = CaptureReader::new(std::io::Cursor::new(writer.into_inner().unwrap())).unwrap();
+
+    let (frame_sender, frame_receiver) = channel();
+    let started = Instant::now();
+    let _replay = VideoStream::replay(capture, 4.0, Duration::from_millis(20), frame_sender).unwrap();
+
+    // The end of the replay flushes all three frames out of the decoder
+    for _ in 0..3 {
+        let frame = frame_receiver.recv_timeout(Duration::from_secs(5)).expect("Replay produced too few frames");
+        assert_eq!((frame.width, frame.height), (80, 48));
+    }
+    assert!(started.elapsed() >= Duration::from_millis(50));
+
+    let (frame_sender, _) = channel();
+    let empty = CaptureReader::new(std::io::Cursor::new(CaptureWriter::new(vec![], 0).unwrap().into_inner().unwrap())).unwrap();
+    assert!(matches!(VideoStream::replay(empty, 0.0, Duration::from_millis(20), frame_sender), Err(VideoError::InvalidReplaySpeed(_))));
+}
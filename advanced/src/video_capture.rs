@@ -0,0 +1,166 @@
+use std::fs::File;
+use std::io::{ self, BufReader, BufWriter, Read, Write };
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+// Captures start with the magic, a version and the length of the header in front of the
+// H.264 data in every packet. The packets follow exactly as received, each prefixed with
+// its arrival time in microseconds since the capture started (u64) and its length (u32).
+const MAGIC: &[u8; 8] = b"TELLOCAP";
+const VERSION: u8 = 1;
+const FILE_HEADER_SIZE: usize = 10;
+const PACKET_HEADER_SIZE: usize = 12;
+// Largest payload of a UDP datagram, longer packets can only come from a corrupt capture
+const MAX_PACKET_SIZE: u32 = 65_507;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedPacket {
+    pub arrival: Duration,
+    pub payload: Vec<u8>,
+}
+
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    started: Instant,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, header_len: usize) -> io::Result<CaptureWriter<BufWriter<File>>> {
+        CaptureWriter::new(BufWriter::new(File::create(path)?), header_len)
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W, header_len: usize) -> io::Result<CaptureWriter<W>> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, header_len as u8])?;
+        Ok(CaptureWriter {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn write_packet(&mut self, payload: &[u8]) -> io::Result<()> {
+        let arrival = self.started.elapsed();
+        self.write_packet_at(arrival, payload)
+    }
+
+    pub fn write_packet_at(&mut self, arrival: Duration, payload: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&(arrival.as_micros() as u64).to_le_bytes())?;
+        self.writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.writer.write_all(payload)
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    header_len: usize,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CaptureReader<BufReader<File>>> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> io::Result<CaptureReader<R>> {
+        let mut header = [0u8; FILE_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC || header[8] != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a Tello video capture"));
+        }
+
+        Ok(CaptureReader {
+            reader,
+            header_len: header[9] as usize,
+        })
+    }
+
+    pub fn header_len(&self) -> usize {
+        self.header_len
+    }
+
+    // A capture cut off in the middle of a packet, e.g. by a crash, simply ends before it
+    pub fn read_packet(&mut self) -> io::Result<Option<CapturedPacket>> {
+        let mut header = [0u8; PACKET_HEADER_SIZE];
+        if !self.read_or_eof(&mut header)? {
+            return Ok(None);
+        }
+
+        let arrival = u64::from_le_bytes([header[0], header[1], header[2], header[3], header[4], header[5], header[6], header[7]]);
+        let length = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        if length > MAX_PACKET_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Captured packet of {} bytes is too long", length)));
+        }
+        let mut payload = vec![0; length as usize];
+        if !self.read_or_eof(&mut payload)? {
+            return Ok(None);
+        }
+
+        Ok(Some(CapturedPacket {
+            arrival: Duration::from_micros(arrival),
+            payload,
+        }))
+    }
+
+    fn read_or_eof(&mut self, buffer: &mut [u8]) -> io::Result<bool> {
+        match self.reader.read_exact(buffer) {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CapturedPacket>;
+
+    fn next(&mut self) -> Option<io::Result<CapturedPacket>> {
+        self.read_packet().transpose()
+    }
+}
+
+#[test]
+fn test_capture_round_trip() {
+    let mut writer = CaptureWriter::new(vec![], 2).unwrap();
+    writer.write_packet_at(Duration::from_micros(0), &[0, 0x80, 1, 2, 3]).unwrap();
+    writer.write_packet_at(Duration::from_millis(33), &[1]).unwrap();
+    writer.write_packet(&[]).unwrap();
+    let capture = writer.into_inner().unwrap();
+    assert!(capture.starts_with(b"TELLOCAP\x01\x02"));
+
+    let mut reader = CaptureReader::new(capture.as_slice()).unwrap();
+    assert_eq!(reader.header_len(), 2);
+    assert_eq!(reader.next().unwrap().unwrap(), CapturedPacket { arrival: Duration::from_micros(0), payload: vec![0, 0x80, 1, 2, 3] });
+    assert_eq!(reader.next().unwrap().unwrap(), CapturedPacket { arrival: Duration::from_millis(33), payload: vec![1] });
+    assert_eq!(reader.next().unwrap().unwrap().payload, vec![]);
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn test_truncated_and_foreign_captures() {
+    let mut writer = CaptureWriter::new(vec![], 0).unwrap();
+    writer.write_packet_at(Duration::from_millis(1), &[1, 2, 3]).unwrap();
+    writer.write_packet_at(Duration::from_millis(2), &[4, 5, 6]).unwrap();
+    let capture = writer.into_inner().unwrap();
+
+    // Cut off in the middle of the second packet
+    let packets: Vec<_> = CaptureReader::new(&capture[..capture.len() - 1]).unwrap().collect();
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].as_ref().unwrap().payload, vec![1, 2, 3]);
+
+    // A corrupt length must not be taken as the size of the next allocation
+    let mut corrupted = capture.clone();
+    corrupted[FILE_HEADER_SIZE + 8..FILE_HEADER_SIZE + PACKET_HEADER_SIZE].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    let mut reader = CaptureReader::new(corrupted.as_slice()).unwrap();
+    assert_eq!(reader.next().unwrap().err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+    assert_eq!(CaptureReader::new(&b"TELLOCAP\x02\x00"[..]).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    assert_eq!(CaptureReader::new(&b"\x00\x00\x00\x01\x67"[..]).err().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+}